@@ -0,0 +1,54 @@
+// Measure the dispatch loop with
+//   cargo run --release --example dispatchbench --features bench_mode
+// Each run prints its result, so redirect stdout to /dev/null.
+//
+// "short" is made of one-word instructions that do almost nothing, so
+// its time is mostly decoding and dispatch. "extended" loads constants
+// whose indices need OP_EXTEND prefixes.
+
+use std::process::exit;
+
+use rlox::{Chunk, Result, Vm};
+
+fn short(len: usize) -> String {
+    let mut listing = String::from("OP_TRUE\n");
+    for _ in 0..len {
+        listing.push_str("OP_NOT\n");
+    }
+    listing.push_str("OP_RETURN\n");
+    listing
+}
+
+fn extended(len: usize) -> String {
+    let mut listing = String::from("OP_NIL\n");
+    for i in 0..len {
+        listing.push_str(&format!("OP_CONSTANT {}\nOP_EQUAL\n", i));
+    }
+    listing.push_str("OP_RETURN\n");
+    listing
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let iterations = match args.len() {
+        1 => 20000,
+        2 => args[1].parse()?,
+        _ => {
+            eprintln!("Usage: dispatchbench [iterations]");
+            exit(1);
+        }
+    };
+
+    let mut vm = Vm::init();
+    for (name, listing) in
+        [("short", short(2000)), ("extended", extended(1000))]
+    {
+        let chunk = Chunk::assemble(&listing)?;
+        #[cfg(feature = "bench_mode")]
+        {
+            let elapsed = vm.bench(&chunk, iterations)?;
+            eprintln!("{:8} {:?}", name, elapsed);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+// Compare value representations with
+//   cargo run --release --example valuebench --features bench_mode
+//   cargo run --release --example valuebench --features bench_mode,nan_boxing
+// Each run prints its result, so redirect stdout to /dev/null.
+//
+// The chunks are assembled rather than compiled so that constant folding
+// does not reduce them to a single value.
+
+use std::process::exit;
+
+use rlox::{Chunk, Result, Vm};
+
+fn numbers(terms: usize) -> String {
+    let mut listing = String::from("OP_CONSTANT 0\n");
+    for i in 0..terms {
+        let op = ["ADD", "SUBTRACT", "MULTIPLY", "DIVIDE"][i % 4];
+        listing.push_str(&format!(
+            "OP_CONSTANT {}.5\nOP_NEGATE\nOP_CONSTANT {}\nOP_ADD\nOP_{}\n",
+            i % 7,
+            i % 3,
+            op
+        ));
+    }
+    listing.push_str("OP_NOT\nOP_TRUE\nOP_EQUAL\nOP_RETURN\n");
+    listing
+}
+
+fn strings(terms: usize) -> String {
+    let mut listing = String::from("OP_CONSTANT ''\n");
+    for i in 0..terms {
+        listing.push_str(&format!("OP_CONSTANT 's{}'\nOP_ADD\n", i % 10));
+    }
+    listing.push_str("OP_RETURN\n");
+    listing
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let iterations = match args.len() {
+        1 => 2000,
+        2 => args[1].parse()?,
+        _ => {
+            eprintln!("Usage: valuebench [iterations]");
+            exit(1);
+        }
+    };
+
+    let mut vm = Vm::init();
+    for (name, listing) in
+        [("numbers", numbers(1000)), ("strings", strings(1000))]
+    {
+        let chunk = Chunk::assemble(&listing)?;
+        #[cfg(feature = "bench_mode")]
+        {
+            let elapsed = vm.bench(&chunk, iterations)?;
+            eprintln!("{:8} {:?}", name, elapsed);
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,23 @@
 use anyhow::bail;
 use num_enum::FromPrimitive;
+use std::collections::HashMap;
 use std::fmt;
+use std::mem::size_of;
+use std::rc::Rc;
 
 use crate::{Result, Value};
 
-type Bytecode = u16;
+mod asm;
+mod disasm;
+mod file;
+mod fold;
+mod peephole;
+mod verify;
+
+pub use disasm::{DisassembledInstruction, Disassembly};
+pub use verify::{VerifyError, VerifyErrorKind};
+
+pub(crate) type Bytecode = u16;
 
 #[derive(
     Copy,
@@ -28,19 +41,105 @@ pub enum Op {
     Equal,
     Greater,
     Less,
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
     Add,
     Subtract,
     Multiply,
     Divide,
+    Constant0,
+    Constant1,
     Constant,
+    AddConstant,
     Extend,
     #[num_enum(default)]
     Unknown,
 }
 
+impl Op {
+    /// Converts a byte of bytecode without the range check done by
+    /// `Op::from_primitive`.
+    ///
+    /// # Safety
+    ///
+    /// `byte` must be the value of one of the variants.
+    pub(crate) unsafe fn from_unchecked(byte: u8) -> Op {
+        debug_assert!(byte <= Op::Unknown as u8);
+        std::mem::transmute::<u8, Op>(byte)
+    }
+
+    /// How many values the instruction pops off the stack.
+    pub fn pops(self) -> usize {
+        match self {
+            Op::Return | Op::Not | Op::Negate | Op::AddConstant => 1,
+            Op::Equal
+            | Op::Greater
+            | Op::Less
+            | Op::NotEqual
+            | Op::GreaterEqual
+            | Op::LessEqual
+            | Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide => 2,
+            Op::Nil
+            | Op::True
+            | Op::False
+            | Op::Constant0
+            | Op::Constant1
+            | Op::Constant
+            | Op::Extend
+            | Op::Unknown => 0,
+        }
+    }
+
+    /// How many values the instruction pushes onto the stack.
+    pub fn pushes(self) -> usize {
+        match self {
+            Op::Return | Op::Extend | Op::Unknown => 0,
+            _ => 1,
+        }
+    }
+
+    /// The net number of values the instruction pushes onto the stack.
+    pub fn stack_effect(self) -> isize {
+        self.pushes() as isize - self.pops() as isize
+    }
+
+    /// Whether the instruction's operand is an index into the constant
+    /// pool.
+    pub fn uses_constant(self) -> bool {
+        matches!(self, Op::Constant | Op::AddConstant)
+    }
+
+    /// Whether the instruction always pushes a boolean.
+    pub fn is_boolean(self) -> bool {
+        matches!(
+            self,
+            Op::True
+                | Op::False
+                | Op::Not
+                | Op::Equal
+                | Op::Greater
+                | Op::Less
+                | Op::NotEqual
+                | Op::GreaterEqual
+                | Op::LessEqual
+        )
+    }
+}
+
 impl fmt::Display for Op {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", format!("OP_{self:?}").to_ascii_uppercase())
+        let mut name = String::from("OP");
+        for c in format!("{self:?}").chars() {
+            if c.is_ascii_uppercase() {
+                name.push('_');
+            }
+            name.push(c.to_ascii_uppercase());
+        }
+        write!(f, "{}", name)
     }
 }
 
@@ -75,10 +174,15 @@ impl Default for Instruction {
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Chunk {
     code: Vec<Bytecode>,
     constants: Vec<Value>,
+    constant_index: ConstantIndex,
     line_map: LineMap,
+    depth: isize,
+    max_stack: usize,
+    prev_max_stack: usize,
 }
 
 impl Default for Chunk {
@@ -94,14 +198,31 @@ impl Chunk {
         Chunk {
             code: Vec::new(),
             constants: Vec::new(),
+            constant_index: ConstantIndex::default(),
             line_map: LineMap::new(),
+            depth: 0,
+            max_stack: 0,
+            prev_max_stack: 0,
         }
     }
 
-    pub fn instructions(&self) -> InstIter {
+    /// The most values the chunk can have on the stack at once.
+    pub fn max_stack(&self) -> usize {
+        self.max_stack
+    }
+
+    pub(crate) fn code(&self) -> &[Bytecode] {
+        &self.code
+    }
+
+    pub fn instructions(&self) -> InstIter<'_> {
+        self.instructions_from(0)
+    }
+
+    pub(crate) fn instructions_from(&self, offset: usize) -> InstIter<'_> {
         InstIter {
             chunk: self,
-            offset: 0,
+            offset,
         }
     }
 
@@ -123,8 +244,14 @@ impl Chunk {
         inst
     }
 
-    pub(crate) fn new_line(&mut self, line: u32) {
-        self.line_map.new_line(line);
+    pub(crate) fn set_location(&mut self, location: Location) {
+        self.line_map.set_location(location);
+    }
+
+    fn track_stack(&mut self, op: Op) {
+        self.depth += op.stack_effect();
+        self.prev_max_stack = self.max_stack;
+        self.max_stack = self.max_stack.max(self.depth.max(0) as usize);
     }
 
     fn push_op(&mut self, op: Op, arg: u8) {
@@ -135,6 +262,7 @@ impl Chunk {
 
     pub(crate) fn write_op(&mut self, op: Op) {
         assert!(op < Op::Constant);
+        self.track_stack(op);
         self.push_op(op, 0);
     }
 
@@ -147,24 +275,176 @@ impl Chunk {
                 self.push_op(Op::Extend, *byte);
             }
         }
+        self.track_stack(op);
         self.push_op(op, arg as u8);
     }
 
+    /// Writes `op`, combining it with the instruction before it into a
+    /// superinstruction where one exists. Instructions are only combined
+    /// when they are on the same line, so errors are still reported on
+    /// the line of the operator.
+    pub(crate) fn write_fused(&mut self, op: Op) {
+        if cfg!(not(feature = "plain_bytecode"))
+            && op == Op::Add
+            && self.fuse_last(Op::Constant, Op::AddConstant)
+        {
+            return;
+        }
+        self.write_op(op);
+    }
+
+    /// Replaces the last instruction with `fused` if it is a `last` on
+    /// the current line. The operand, along with any `OP_EXTEND` words
+    /// before it, is kept.
+    fn fuse_last(&mut self, last: Op, fused: Op) -> bool {
+        let Some(word) = self.code.last_mut() else {
+            return false;
+        };
+        let [opcode, arg] = word.to_be_bytes();
+        if Op::from_primitive(opcode) != last
+            || !self.line_map.last_on_current_line()
+        {
+            return false;
+        }
+        *word = u16::from_be_bytes([fused as u8, arg]);
+        self.depth += fused.stack_effect() - last.stack_effect();
+        self.max_stack = self.prev_max_stack.max(self.depth.max(0) as usize);
+        true
+    }
+
+    /// Writes the shortest instruction that pushes `value`.
+    pub(crate) fn write_value(&mut self, value: Value) -> Result<()> {
+        match value {
+            Value::Nil => self.write_op(Op::Nil),
+            Value::Boolean(true) => self.write_op(Op::True),
+            Value::Boolean(false) => self.write_op(Op::False),
+            #[cfg(not(feature = "plain_bytecode"))]
+            Value::Number(n) if n.to_bits() == 0f64.to_bits() => {
+                self.write_op(Op::Constant0)
+            }
+            #[cfg(not(feature = "plain_bytecode"))]
+            Value::Number(n) if n == 1.0 => self.write_op(Op::Constant1),
+            _ => {
+                let idx = self.add_constant(value)?;
+                self.write_op_arg(Op::Constant, idx);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `value` to the constant pool, returning its index. Numbers
+    /// with the same bits and equal strings share a single entry.
     pub(crate) fn add_constant(&mut self, value: Value) -> Result<u32> {
+        if let Some(idx) = self.constant_index.find(&value) {
+            return Ok(idx);
+        }
+        self.push_constant(value)
+    }
+
+    /// Adds `value` as a new entry at the end of the pool, even if an
+    /// equal one is already in it.
+    pub(crate) fn push_constant(&mut self, value: Value) -> Result<u32> {
         let idx = self.constants.len();
         if idx > Chunk::MAX_CONSTS {
             bail!("too many constants in one chunk")
         }
+        self.constant_index.insert(&value, idx as u32);
         self.constants.push(value);
         Ok(idx as u32)
     }
 
+    /// Sizes of the constant pool.
+    pub fn pool_stats(&self) -> PoolStats {
+        let mut stats = PoolStats {
+            len: self.constants.len(),
+            ..PoolStats::default()
+        };
+        for constant in &self.constants {
+            match constant {
+                Value::Number(_) => stats.numbers += 1,
+                Value::String(s) => {
+                    stats.strings += 1;
+                    stats.string_bytes += s.len();
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+
     pub(crate) fn get_line(&self, offset: usize) -> u32 {
-        self.line_map.get_line(offset)
+        self.line_map.get_location(offset).line
+    }
+
+    pub(crate) fn get_location(&self, offset: usize) -> Location {
+        self.line_map.get_location(offset)
     }
 
-    pub(crate) fn get_constant(&self, idx: u32) -> Value {
-        self.constants[idx as usize].clone()
+    /// Bytes of heap memory owned by the chunk, including the contents of
+    /// string constants.
+    pub(crate) fn heap_size(&self) -> usize {
+        let strings: usize = self
+            .constants
+            .iter()
+            .map(|c| match c {
+                Value::String(s) => s.len(),
+                _ => 0,
+            })
+            .sum();
+        self.code.len() * size_of::<Bytecode>()
+            + self.constants.len() * size_of::<Value>()
+            + strings
+            + self.line_map.heap_size()
+    }
+
+    pub(crate) fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PoolStats {
+    /// Entries in the pool.
+    pub len: usize,
+    pub numbers: usize,
+    pub strings: usize,
+    /// Total length of the string constants.
+    pub string_bytes: usize,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum ConstantKey {
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl ConstantKey {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Some(ConstantKey::Number(n.to_bits())),
+            Value::String(s) => Some(ConstantKey::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// The position of each number and string in a chunk's constant pool.
+/// Nil and booleans have their own instructions, so they are not indexed.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ConstantIndex {
+    entries: HashMap<ConstantKey, u32>,
+}
+
+impl ConstantIndex {
+    fn find(&self, value: &Value) -> Option<u32> {
+        self.entries.get(&ConstantKey::of(value)?).copied()
+    }
+
+    /// Records that `value` is at `idx`, unless it is already in the pool.
+    fn insert(&mut self, value: &Value, idx: u32) {
+        if let Some(key) = ConstantKey::of(value) {
+            self.entries.entry(key).or_insert(idx);
+        }
     }
 }
 
@@ -186,9 +466,53 @@ impl<'a> Iterator for InstIter<'a> {
     }
 }
 
+/// A position in the source. Columns are byte offsets into the line,
+/// counting from 1, or 0 when not known.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Location {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Location {
+    pub(crate) fn new(line: u32, column: u32) -> Self {
+        Location { line, column }
+    }
+}
+
+/// Maps bytecode offsets to source locations.
+///
+/// Consecutive words with the same location form a run. Finished runs are
+/// delta encoded as three LEB128 numbers: the number of words, the change
+/// in line from the run before (zigzag encoded, since folded code can go
+/// back a line), and the column. The position of every
+/// `CHECKPOINT_INTERVAL`th run is kept so a lookup only has to decode a
+/// few runs after a binary search.
+#[derive(Debug)]
 struct LineMap {
-    lines: Vec<u32>,
-    current_line: u32,
+    table: Vec<u8>,
+    checkpoints: Vec<Checkpoint>,
+    runs: usize,
+    encoded_len: usize,
+    /// The line of the last encoded run, which the next is relative to.
+    last_line: u32,
+    /// The run new words are added to, which is encoded once it ends.
+    open: Location,
+    open_len: usize,
+    current: Location,
+}
+
+#[derive(Debug)]
+struct Checkpoint {
+    offset: usize,
+    line: u32,
+    pos: usize,
+}
+
+impl PartialEq for LineMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.runs().eq(other.runs())
+    }
 }
 
 impl Default for LineMap {
@@ -198,59 +522,153 @@ impl Default for LineMap {
 }
 
 impl LineMap {
+    const CHECKPOINT_INTERVAL: usize = 16;
+
     fn new() -> Self {
         LineMap {
-            lines: Vec::new(),
-            current_line: 1,
+            table: Vec::new(),
+            checkpoints: Vec::new(),
+            runs: 0,
+            encoded_len: 0,
+            last_line: 0,
+            open: Location::new(1, 0),
+            open_len: 0,
+            current: Location::new(1, 0),
         }
     }
 
-    fn new_line(&mut self, line: u32) {
-        self.current_line = line;
+    fn set_location(&mut self, location: Location) {
+        self.current = location;
     }
 
     fn add_op(&mut self) {
-        self.lines.push(self.current_line);
+        self.add_run(1, self.current);
     }
 
-    fn get_line(&self, offset: usize) -> u32 {
-        self.lines[offset]
+    /// Adds `len` words at `location`, extending the last run if it has
+    /// the same location.
+    fn add_run(&mut self, len: usize, location: Location) {
+        if self.open_len > 0 && self.open == location {
+            self.open_len += len;
+            return;
+        }
+        self.finish_run();
+        self.open = location;
+        self.open_len = len;
     }
-}
 
-#[cfg(debug_assertions)]
-impl Chunk {
-    pub fn disassemble(&self, name: &str) {
-        println!("== {name} ==");
-        let mut offset = 0;
-        for inst in self.instructions() {
-            print!("{:4} ", self.get_line(offset));
-            self.disassemble_instruction(inst, offset);
-            offset += inst.len;
+    fn finish_run(&mut self) {
+        if self.open_len == 0 {
+            return;
+        }
+        if self.runs.is_multiple_of(LineMap::CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(Checkpoint {
+                offset: self.encoded_len,
+                line: self.last_line,
+                pos: self.table.len(),
+            });
         }
+        let delta = self.open.line as i64 - self.last_line as i64;
+        write_varint(&mut self.table, self.open_len as u64);
+        write_varint(&mut self.table, ((delta << 1) ^ (delta >> 63)) as u64);
+        write_varint(&mut self.table, self.open.column as u64);
+        self.last_line = self.open.line;
+        self.encoded_len += self.open_len;
+        self.runs += 1;
     }
 
-    pub fn disassemble_instruction(&self, inst: Instruction, offset: usize) {
-        print!("{:04} ", offset);
-        match inst.opcode {
-            op if op < Op::Constant => {
-                println!("{}", op);
-            }
-            Op::Constant => {
-                self.disassemble_const(inst.operand);
-            }
-            _ => {
-                println!("Unknown opcode {}", inst.opcode as u8);
+    /// Whether the last word added is on the current line.
+    fn last_on_current_line(&self) -> bool {
+        self.open_len > 0 && self.open.line == self.current.line
+    }
+
+    fn get_location(&self, offset: usize) -> Location {
+        assert!(offset < self.len());
+        if offset >= self.encoded_len {
+            return self.open;
+        }
+        let idx = self.checkpoints.partition_point(|c| c.offset <= offset);
+        let checkpoint = &self.checkpoints[idx - 1];
+        let mut start = checkpoint.offset;
+        let runs = Runs {
+            table: &self.table,
+            pos: checkpoint.pos,
+            line: checkpoint.line,
+        };
+        for (len, location) in runs {
+            if offset < start + len {
+                return location;
             }
+            start += len;
+        }
+        unreachable!()
+    }
+
+    /// The number of words mapped.
+    fn len(&self) -> usize {
+        self.encoded_len + self.open_len
+    }
+
+    /// The length and location of each run.
+    fn runs(&self) -> impl Iterator<Item = (usize, Location)> + '_ {
+        let runs = Runs {
+            table: &self.table,
+            pos: 0,
+            line: 0,
+        };
+        let open = (self.open_len > 0).then_some((self.open_len, self.open));
+        runs.chain(open)
+    }
+
+    fn heap_size(&self) -> usize {
+        self.table.len() + self.checkpoints.len() * size_of::<Checkpoint>()
+    }
+}
+
+/// Decodes the runs of a `LineMap` table.
+struct Runs<'a> {
+    table: &'a [u8],
+    pos: usize,
+    line: u32,
+}
+
+impl Iterator for Runs<'_> {
+    type Item = (usize, Location);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.table.len() {
+            return None;
         }
+        let len = read_varint(self.table, &mut self.pos) as usize;
+        let zigzag = read_varint(self.table, &mut self.pos) as i64;
+        let column = read_varint(self.table, &mut self.pos) as u32;
+        let delta = (zigzag >> 1) ^ -(zigzag & 1);
+        self.line = (self.line as i64 + delta) as u32;
+        Some((len, Location::new(self.line, column)))
+    }
+}
+
+fn write_varint(table: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        table.push(value as u8 | 0x80);
+        value >>= 7;
     }
+    table.push(value as u8);
+}
 
-    fn disassemble_const(&self, arg: u32) {
-        print!("{:10} {:08} ", format!("{}", Op::Constant), arg);
-        if arg as usize >= self.constants.len() {
-            println!("(out of range)");
-        } else {
-            println!("{}", self.constants[arg as usize]);
+fn read_varint(table: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = table[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return value;
         }
+        shift += 7;
     }
 }
+
+#[cfg(test)]
+mod test;
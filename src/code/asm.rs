@@ -0,0 +1,216 @@
+use anyhow::{anyhow, bail};
+
+use super::{Chunk, Location, Op};
+use crate::{Result, Value};
+
+impl Op {
+    fn from_name(name: &str) -> Option<Op> {
+        (0..Op::Extend as u8)
+            .map(Op::from)
+            .find(|op| op.to_string() == name)
+    }
+}
+
+impl Chunk {
+    /// Builds a chunk from a listing in the format written by
+    /// `disassembly`. Each instruction is a line of the form
+    ///
+    /// ```text
+    /// [line[:column] [offset]] OP_NAME [[index] constant]
+    /// ```
+    ///
+    /// An instruction without a location is on the same line as the one
+    /// before it. Offsets are ignored, since they follow from the
+    /// instructions. A constant without an index shares an equal entry or
+    /// is added to the end of the pool, and one with an index must match
+    /// that entry or be the next one. Blank lines, `== name ==` headers and
+    /// text after a `;` are skipped. The result is verified like a chunk
+    /// loaded from a file.
+    pub fn assemble(listing: &str) -> Result<Chunk> {
+        let mut asm = Assembler {
+            chunk: Chunk::new(),
+        };
+        for (idx, text) in listing.lines().enumerate() {
+            asm.line(text)
+                .map_err(|e| anyhow!("line {}: {}", idx + 1, e))?;
+        }
+        asm.finish()
+    }
+}
+
+struct Assembler {
+    chunk: Chunk,
+}
+
+impl Assembler {
+    fn line(&mut self, text: &str) -> Result<()> {
+        if text.trim_start().starts_with("==") {
+            return Ok(());
+        }
+        let tokens = tokenize(text)?;
+        let mut tokens = tokens.as_slice();
+        let located = match tokens.first().and_then(|t| parse_location(t)) {
+            Some(location) => {
+                self.chunk.set_location(location);
+                tokens = &tokens[1..];
+                if tokens.first().is_some_and(|t| is_index(t)) {
+                    tokens = &tokens[1..];
+                }
+                true
+            }
+            None => false,
+        };
+        let (name, operands) = match tokens.split_first() {
+            Some(split) => split,
+            None if !located => return Ok(()),
+            None => bail!("expected an instruction"),
+        };
+
+        let op = Op::from_name(name)
+            .ok_or_else(|| anyhow!("unknown instruction '{}'", name))?;
+        if op < Op::Constant {
+            if !operands.is_empty() {
+                bail!("{} takes no operand", op);
+            }
+            self.chunk.write_op(op);
+            return Ok(());
+        }
+
+        let idx = match operands {
+            [value] => self.chunk.add_constant(parse_constant(value)?)?,
+            [idx, value] if is_index(idx) => {
+                self.define(idx.parse()?, parse_constant(value)?)?
+            }
+            _ => bail!("expected [index] constant after {}", op),
+        };
+        self.chunk.write_op_arg(op, idx);
+        Ok(())
+    }
+
+    /// Defines the constant at `idx`, which must be an existing entry with
+    /// the same value or the next one in the pool.
+    fn define(&mut self, idx: u32, value: Value) -> Result<u32> {
+        let pos = idx as usize;
+        let constants = &self.chunk.constants;
+        match constants.get(pos) {
+            Some(existing) if same_constant(existing, &value) => Ok(idx),
+            Some(existing) => bail!("constant {} is already {}", idx, existing),
+            None if pos == constants.len() => self.chunk.push_constant(value),
+            None => bail!("expected constant {}, not {}", constants.len(), idx),
+        }
+    }
+
+    fn finish(self) -> Result<Chunk> {
+        self.chunk.verify()?;
+        Ok(self.chunk)
+    }
+}
+
+fn same_constant(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+fn is_index(token: &str) -> bool {
+    token.bytes().all(|c| c.is_ascii_digit())
+}
+
+fn parse_location(token: &str) -> Option<Location> {
+    let (line, column) = token.split_once(':').unwrap_or((token, "0"));
+    if !is_index(line) || !is_index(column) {
+        return None;
+    }
+    Some(Location::new(line.parse().ok()?, column.parse().ok()?))
+}
+
+/// Splits a line into whitespace-separated tokens, keeping quoted strings
+/// whole and dropping a trailing comment.
+fn tokenize(text: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '\'' {
+            let mut token = String::new();
+            token.push(chars.next().unwrap());
+            loop {
+                match chars.next() {
+                    None => bail!("unterminated string"),
+                    Some('\'') => break,
+                    Some('\\') => {
+                        token.push('\\');
+                        token.extend(chars.next());
+                    }
+                    Some(c) => token.push(c),
+                }
+            }
+            token.push('\'');
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_constant(token: &str) -> Result<Value> {
+    if let Some(quoted) = token.strip_prefix('\'') {
+        let text = &quoted[..quoted.len() - 1];
+        return Ok(Value::String(unescape(text)?.into()));
+    }
+    Ok(match token {
+        "nil" => Value::Nil,
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => Value::Number(
+            token
+                .parse()
+                .map_err(|_| anyhow!("invalid constant '{}'", token))?,
+        ),
+    })
+}
+
+fn unescape(text: &str) -> Result<String> {
+    let mut s = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => s.push('\\'),
+            Some('\'') => s.push('\''),
+            Some('n') => s.push('\n'),
+            Some('r') => s.push('\r'),
+            Some('t') => s.push('\t'),
+            Some('u') => {
+                let rest = chars.as_str();
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|r| r.split_once('}'))
+                    .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| anyhow!("invalid unicode escape"))?;
+                s.push(code);
+                let end = rest.find('}').unwrap();
+                chars = rest[end + 1..].chars();
+            }
+            _ => bail!("invalid escape in string"),
+        }
+    }
+    Ok(s)
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+
+use super::{Chunk, Location, Op};
+use crate::Value;
+
+/// One decoded instruction, as listed by `Chunk::disassemble`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisassembledInstruction {
+    /// Offset of the instruction's first word, including any prefixes.
+    pub offset: usize,
+    pub line: u32,
+    /// The source column, counting from 1, or 0 if it is not known.
+    pub column: u32,
+    pub opcode: Op,
+    pub operand: Option<u32>,
+    /// The constant an `OP_CONSTANT` loads, if its index is in range.
+    pub constant: Option<Value>,
+    /// Number of `OP_EXTEND` words before the opcode.
+    pub extend_len: usize,
+}
+
+/// Formats a constant for a listing. Strings are quoted so they can be
+/// told apart from other values when assembling.
+fn constant_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => {
+            let mut text = String::from("'");
+            for c in s.chars() {
+                match c {
+                    '\\' => text.push_str("\\\\"),
+                    '\'' => text.push_str("\\'"),
+                    '\n' => text.push_str("\\n"),
+                    '\r' => text.push_str("\\r"),
+                    '\t' => text.push_str("\\t"),
+                    c if c.is_control() => {
+                        text.push_str(&format!("\\u{{{:x}}}", c as u32))
+                    }
+                    c => text.push(c),
+                }
+            }
+            text.push('\'');
+            text
+        }
+        _ => value.to_string(),
+    }
+}
+
+impl DisassembledInstruction {
+    pub(crate) fn location(&self) -> Location {
+        Location::new(self.line, self.column)
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    /// Writes the instruction without its line number, as traced while
+    /// executing.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04} ", self.offset)?;
+        match (self.opcode, self.operand) {
+            (Op::Unknown, _) => {
+                write!(f, "Unknown opcode {}", self.opcode as u8)
+            }
+            (op, None) => write!(f, "{}", op),
+            (op, Some(operand)) => {
+                write!(f, "{:10} {:08} ", format!("{}", op), operand)?;
+                match &self.constant {
+                    Some(value) => write!(f, "{}", constant_text(value)),
+                    None => write!(f, "(out of range)"),
+                }
+            }
+        }
+    }
+}
+
+/// Writes a chunk's listing with a header and source locations; returned by
+/// `Chunk::disassembly`.
+pub struct Disassembly<'a> {
+    chunk: &'a Chunk,
+    name: &'a str,
+}
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "== {} ==", self.name)?;
+        for inst in self.chunk.disassemble() {
+            let location = match inst.column {
+                0 => inst.line.to_string(),
+                column => format!("{}:{}", inst.line, column),
+            };
+            writeln!(f, "{:>4} {}", location, inst)?;
+        }
+        Ok(())
+    }
+}
+
+impl Chunk {
+    pub fn disassemble(&self) -> Vec<DisassembledInstruction> {
+        let mut offset = 0;
+        let mut listing = Vec::new();
+        while offset < self.code.len() {
+            let inst = self.disassemble_at(offset);
+            offset += inst.extend_len + 1;
+            listing.push(inst);
+        }
+        listing
+    }
+
+    /// Decodes the instruction starting at `offset`.
+    pub fn disassemble_at(&self, offset: usize) -> DisassembledInstruction {
+        let inst = self.get_instruction(offset);
+        let location = self.get_location(offset);
+        let operand = (inst.opcode >= Op::Constant && inst.opcode < Op::Extend)
+            .then_some(inst.operand);
+        let constant = inst
+            .opcode
+            .uses_constant()
+            .then(|| self.constants.get(inst.operand as usize).cloned())
+            .flatten();
+        DisassembledInstruction {
+            offset,
+            line: location.line,
+            column: location.column,
+            opcode: inst.opcode,
+            operand,
+            constant,
+            extend_len: inst.len - 1,
+        }
+    }
+
+    pub fn disassembly<'a>(&'a self, name: &'a str) -> Disassembly<'a> {
+        Disassembly { chunk: self, name }
+    }
+}
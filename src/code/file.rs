@@ -0,0 +1,149 @@
+//! The compiled chunk file format.
+//!
+//! All integers are little-endian. A file is the magic bytes and a `u16`
+//! format version, followed by three sections, each starting with a `u32`
+//! count: the bytecode words, the constant pool, and the line table.
+//! Constants are a tag byte followed by the number's bits or a string's
+//! length and UTF-8 bytes. The line table is a list of runs, each a `u32`
+//! number of bytecode words followed by their `u32` line and column.
+
+use anyhow::{bail, Context};
+use num_enum::FromPrimitive;
+use std::io::{Read, Write};
+
+use super::{Chunk, Location, Op};
+use crate::{Result, Value};
+
+const FORMAT_VERSION: u16 = 4;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+impl Chunk {
+    pub const MAGIC: &'static [u8; 4] = b"LOXC";
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(Chunk::MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+
+        write_len(w, self.code.len())?;
+        for word in &self.code {
+            w.write_all(&word.to_le_bytes())?;
+        }
+
+        write_len(w, self.constants.len())?;
+        for constant in &self.constants {
+            match constant {
+                Value::Nil => w.write_all(&[TAG_NIL])?,
+                Value::Boolean(false) => w.write_all(&[TAG_FALSE])?,
+                Value::Boolean(true) => w.write_all(&[TAG_TRUE])?,
+                Value::Number(v) => {
+                    w.write_all(&[TAG_NUMBER])?;
+                    w.write_all(&v.to_bits().to_le_bytes())?;
+                }
+                Value::String(v) => {
+                    w.write_all(&[TAG_STRING])?;
+                    write_len(w, v.len())?;
+                    w.write_all(v.as_bytes())?;
+                }
+            }
+        }
+
+        let runs: Vec<_> = self.line_map.runs().collect();
+        write_len(w, runs.len())?;
+        for (len, location) in runs {
+            write_len(w, len)?;
+            w.write_all(&location.line.to_le_bytes())?;
+            w.write_all(&location.column.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Loads a chunk written by `write_to`, verifying it before returning.
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Chunk> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic).context("not a compiled chunk")?;
+        if &magic != Chunk::MAGIC {
+            bail!("not a compiled chunk");
+        }
+        let version = u16::from_le_bytes(read_bytes(r)?);
+        if version != FORMAT_VERSION {
+            bail!("unsupported chunk format version {}", version);
+        }
+
+        let mut chunk = Chunk::new();
+        let len = read_len(r)?;
+        for _ in 0..len {
+            let word = u16::from_le_bytes(read_bytes(r)?);
+            let op = Op::from_primitive(word.to_be_bytes()[0]);
+            if op != Op::Extend {
+                chunk.track_stack(op);
+            }
+            chunk.code.push(word);
+        }
+
+        let len = read_len(r)?;
+        if len > Chunk::MAX_CONSTS + 1 {
+            bail!("too many constants in one chunk");
+        }
+        for _ in 0..len {
+            let value = match read_bytes::<_, 1>(r)?[0] {
+                TAG_NIL => Value::Nil,
+                TAG_FALSE => Value::Boolean(false),
+                TAG_TRUE => Value::Boolean(true),
+                TAG_NUMBER => Value::Number(f64::from_bits(
+                    u64::from_le_bytes(read_bytes(r)?),
+                )),
+                TAG_STRING => {
+                    let len = read_len(r)?;
+                    let mut bytes = Vec::new();
+                    r.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+                    if bytes.len() != len {
+                        bail!("unexpected end of chunk");
+                    }
+                    Value::String(String::from_utf8(bytes)?.into())
+                }
+                tag => bail!("unknown constant tag {}", tag),
+            };
+            let idx = chunk.constants.len() as u32;
+            chunk.constant_index.insert(&value, idx);
+            chunk.constants.push(value);
+        }
+
+        let runs = read_len(r)?;
+        for _ in 0..runs {
+            let len = read_len(r)?;
+            let line = u32::from_le_bytes(read_bytes(r)?);
+            let column = u32::from_le_bytes(read_bytes(r)?);
+            if len > chunk.code.len() - chunk.line_map.len() {
+                bail!("line table does not match bytecode");
+            }
+            chunk.line_map.add_run(len, Location::new(line, column));
+        }
+        if chunk.line_map.len() != chunk.code.len() {
+            bail!("line table does not match bytecode");
+        }
+        chunk.verify()?;
+        Ok(chunk)
+    }
+}
+
+fn write_len<W: Write>(w: &mut W, len: usize) -> Result<()> {
+    let len = u32::try_from(len).context("section too large")?;
+    w.write_all(&len.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)
+        .context("unexpected end of chunk")?;
+    Ok(bytes)
+}
+
+fn read_len<R: Read>(r: &mut R) -> Result<usize> {
+    Ok(u32::from_le_bytes(read_bytes(r)?) as usize)
+}
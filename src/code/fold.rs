@@ -0,0 +1,105 @@
+use super::{Chunk, Location, Op};
+use crate::{Result, Value};
+
+impl Op {
+    /// Evaluates the instruction on constant operands, or returns `None`
+    /// if it must be left for runtime, either because it would fail or
+    /// because it is not a pure operation on its operands.
+    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+    fn fold(self, args: &[Value]) -> Option<Value> {
+        use Value::{Boolean, Number};
+
+        let result = match (self, args) {
+            (Op::Not, [a]) => Some(Boolean(!bool::from(a.clone()))),
+            (Op::Negate, [Number(a)]) => Some(Number(-a)),
+            (Op::Equal, [a, b]) => Some(Boolean(a == b)),
+            (Op::Greater, [Number(a), Number(b)]) => Some(Boolean(a > b)),
+            (Op::Less, [Number(a), Number(b)]) => Some(Boolean(a < b)),
+            (Op::NotEqual, [a, b]) => Some(Boolean(a != b)),
+            (Op::GreaterEqual, [Number(a), Number(b)]) => {
+                Some(Boolean(!(a < b)))
+            }
+            (Op::LessEqual, [Number(a), Number(b)]) => Some(Boolean(!(a > b))),
+            (Op::Add, [Number(a), Number(b)]) => Some(Number(a + b)),
+            (Op::Add, [Value::String(a), Value::String(b)]) => {
+                Some(Value::String([&**a, &**b].concat().into()))
+            }
+            (Op::Subtract, [Number(a), Number(b)]) => Some(Number(a - b)),
+            (Op::Multiply, [Number(a), Number(b)]) => Some(Number(a * b)),
+            (Op::Divide, [Number(a), Number(b)]) => Some(Number(a / b)),
+            _ => None,
+        };
+        // A NaN is not equal to itself, so a chunk holding one would not
+        // compare equal to its own listing or file. It is made at runtime
+        // instead.
+        result.filter(|value| !matches!(value, Number(n) if n.is_nan()))
+    }
+}
+
+impl Chunk {
+    /// Returns a copy of the chunk in which operations on literal values
+    /// are evaluated at compile time. Operations that would fail at
+    /// runtime, like negating a string, are kept so the error is still
+    /// reported when the code runs.
+    pub(crate) fn fold_constants(&self) -> Result<Chunk> {
+        let mut out = Chunk::new();
+        // Values whose load has not been written yet. They are always the
+        // top of the stack, so operations can consume them directly.
+        let mut pending: Vec<(Value, Location)> = Vec::new();
+
+        for inst in self.disassemble() {
+            let value = match inst.opcode {
+                Op::Nil => Some(Value::Nil),
+                Op::True => Some(Value::Boolean(true)),
+                Op::False => Some(Value::Boolean(false)),
+                Op::Constant0 => Some(Value::Number(0.0)),
+                Op::Constant1 => Some(Value::Number(1.0)),
+                Op::Constant => inst.constant.clone(),
+                _ => None,
+            };
+            if let Some(value) = value {
+                pending.push((value, inst.location()));
+                continue;
+            }
+
+            // A superinstruction is folded as the instructions it stands
+            // for, and recombined when it is written out.
+            let location = inst.location();
+            let (op, operand) = match (inst.opcode, inst.constant) {
+                (Op::AddConstant, Some(value)) => {
+                    pending.push((value, location));
+                    (Op::Add, None)
+                }
+                (op, _) => (op, inst.operand),
+            };
+
+            let arity = op.pops();
+            if arity > 0 && pending.len() >= arity {
+                let start = pending.len() - arity;
+                let args: Vec<Value> =
+                    pending[start..].iter().map(|(v, _)| v.clone()).collect();
+                if let Some(result) = op.fold(&args) {
+                    pending.truncate(start);
+                    pending.push((result, location));
+                    continue;
+                }
+            }
+
+            for (value, location) in pending.drain(..) {
+                out.set_location(location);
+                out.write_value(value)?;
+            }
+            out.set_location(location);
+            match operand {
+                Some(operand) => out.write_op_arg(op, operand),
+                None => out.write_fused(op),
+            }
+        }
+
+        for (value, location) in pending {
+            out.set_location(location);
+            out.write_value(value)?;
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,61 @@
+use super::{Chunk, Location, Op};
+
+impl Op {
+    /// The single instruction equivalent to `self` followed by `Op::Not`.
+    fn negated(self) -> Option<Op> {
+        match self {
+            Op::Equal => Some(Op::NotEqual),
+            Op::Less => Some(Op::GreaterEqual),
+            Op::Greater => Some(Op::LessEqual),
+            Op::NotEqual => Some(Op::Equal),
+            Op::GreaterEqual => Some(Op::Less),
+            Op::LessEqual => Some(Op::Greater),
+            _ => None,
+        }
+    }
+}
+
+impl Chunk {
+    /// Returns a copy of the chunk with short instruction sequences
+    /// replaced by cheaper equivalents:
+    ///
+    /// - a comparison followed by `OP_NOT` becomes the opposite comparison
+    ///   (`OP_EQUAL OP_NOT` is `OP_NOT_EQUAL`, and so on);
+    /// - `OP_NOT OP_NOT` is dropped after an instruction that pushes a
+    ///   boolean, where it has no effect;
+    /// - instructions after an `OP_RETURN` are removed, as there are no
+    ///   jumps that could reach them.
+    pub(crate) fn peephole(&self) -> Chunk {
+        let mut insts: Vec<(Op, Option<u32>, Location)> = Vec::new();
+        for inst in self.disassemble() {
+            let last = insts.last().map(|&(op, _, _)| op);
+            match (last, inst.opcode) {
+                (Some(prev), Op::Not) if prev.negated().is_some() => {
+                    insts.last_mut().unwrap().0 = prev.negated().unwrap();
+                }
+                (Some(Op::Not), Op::Not)
+                    if insts.len() >= 2
+                        && insts[insts.len() - 2].0.is_boolean() =>
+                {
+                    insts.pop();
+                }
+                _ => insts.push((inst.opcode, inst.operand, inst.location())),
+            }
+            if inst.opcode == Op::Return {
+                break;
+            }
+        }
+
+        let mut out = Chunk::new();
+        out.constants = self.constants.clone();
+        out.constant_index = self.constant_index.clone();
+        for (op, operand, location) in insts {
+            out.set_location(location);
+            match operand {
+                Some(operand) => out.write_op_arg(op, operand),
+                None => out.write_op(op),
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,522 @@
+use super::{Chunk, Location, Op, PoolStats, VerifyErrorKind};
+use crate::{Parser, Result, Value, Vm};
+
+fn compile(source: &str) -> Chunk {
+    Parser::new(source.to_string())
+        .parse(&mut Vm::init())
+        .unwrap()
+}
+
+#[test]
+fn file_round_trip() -> Result<()> {
+    let chunk = compile("-(1 + 2) * \"a\"\n == \n\"multi\nline\" + nil");
+    let mut bytes = Vec::new();
+    chunk.write_to(&mut bytes)?;
+    assert!(bytes.starts_with(Chunk::MAGIC));
+
+    let loaded = Chunk::read_from(&mut bytes.as_slice())?;
+    assert_eq!(chunk, loaded);
+
+    Ok(())
+}
+
+#[test]
+fn file_wide_operands() -> Result<()> {
+    let mut chunk = Chunk::new();
+    for i in 0..300 {
+        let idx = chunk.add_constant(Value::Number(i as f64))?;
+        chunk.write_op_arg(Op::Constant, idx);
+        chunk.write_op(Op::Return);
+    }
+    let mut bytes = Vec::new();
+    chunk.write_to(&mut bytes)?;
+    assert_eq!(chunk, Chunk::read_from(&mut bytes.as_slice())?);
+
+    Ok(())
+}
+
+#[test]
+fn file_errors() -> Result<()> {
+    let mut bytes = Vec::new();
+    compile("\"abc\"").write_to(&mut bytes)?;
+
+    assert!(Chunk::read_from(&mut &b"LOX"[..]).is_err());
+    assert!(Chunk::read_from(&mut &b"XOXC\x01\x00"[..]).is_err());
+    let mut version = bytes.clone();
+    version[4] = 0xff;
+    assert!(Chunk::read_from(&mut version.as_slice()).is_err());
+    for len in 0..bytes.len() {
+        assert!(Chunk::read_from(&mut &bytes[..len]).is_err());
+    }
+
+    Ok(())
+}
+
+fn raw_chunk(words: &[(u8, u8)], constants: usize) -> Chunk {
+    let mut chunk = Chunk::new();
+    for i in 0..constants {
+        chunk.add_constant(Value::Number(i as f64)).unwrap();
+    }
+    for &(op, arg) in words {
+        chunk.code.push(u16::from_be_bytes([op, arg]));
+        chunk.line_map.add_op();
+    }
+    chunk
+}
+
+fn verify_error(
+    words: &[(u8, u8)],
+    constants: usize,
+) -> (usize, VerifyErrorKind) {
+    let err = raw_chunk(words, constants).verify().unwrap_err();
+    (err.offset(), err.kind())
+}
+
+#[test]
+fn verify_compiled_code() {
+    assert!(compile("-(1 + 2) * \"a\" == !nil").verify().is_ok());
+}
+
+#[test]
+fn verify_errors() {
+    let nil = Op::Nil as u8;
+    let add = Op::Add as u8;
+    let constant = Op::Constant as u8;
+    let extend = Op::Extend as u8;
+
+    assert_eq!(
+        (1, VerifyErrorKind::UnknownOpcode(0xfe)),
+        verify_error(&[(nil, 0), (0xfe, 0)], 0)
+    );
+    assert_eq!(
+        (1, VerifyErrorKind::UnexpectedExtend(Op::Nil)),
+        verify_error(&[(nil, 0), (extend, 1), (nil, 0)], 0)
+    );
+    assert_eq!(
+        (0, VerifyErrorKind::DanglingExtend),
+        verify_error(&[(extend, 1), (extend, 1)], 0)
+    );
+    assert_eq!(
+        (0, VerifyErrorKind::OperandOverflow),
+        verify_error(
+            &[
+                (extend, 1),
+                (extend, 0),
+                (extend, 0),
+                (extend, 0),
+                (constant, 0)
+            ],
+            0
+        )
+    );
+    assert_eq!(
+        (
+            1,
+            VerifyErrorKind::ConstantOutOfRange {
+                index: 0x102,
+                len: 2
+            }
+        ),
+        verify_error(&[(constant, 1), (extend, 1), (constant, 2)], 2)
+    );
+    assert_eq!(
+        (3, VerifyErrorKind::StackUnderflow(Op::Add)),
+        verify_error(&[(nil, 0), (nil, 0), (add, 0), (add, 0)], 0)
+    );
+}
+
+#[test]
+fn read_verifies() -> Result<()> {
+    let chunk = raw_chunk(&[(Op::Return as u8, 0)], 0);
+    let mut bytes = Vec::new();
+    chunk.write_to(&mut bytes)?;
+    let err = Chunk::read_from(&mut bytes.as_slice()).unwrap_err();
+    assert!(err.downcast_ref::<super::VerifyError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn assemble_round_trip() -> Result<()> {
+    for source in [
+        "1",
+        "-(1 + 2) * 3 / 4 - 5",
+        "!(1 < 2) == (3 >= 4) != (5 <= 6 == nil)",
+        "\"it's\" + \"a\\tb\"\n+ \"multi\nline;\" == true",
+        "0.1 + 100000000 * 0.3 / 0",
+    ] {
+        let chunk = compile(source);
+        let listing = chunk.disassembly("test").to_string();
+        assert_eq!(chunk, Chunk::assemble(&listing)?, "{}", listing);
+    }
+
+    let mut chunk = Chunk::new();
+    for i in 0..300 {
+        let idx = chunk.add_constant(Value::String(format!("s{i}").into()))?;
+        chunk.set_location(Location::new(i / 7 + 1, 0));
+        chunk.write_op_arg(Op::Constant, idx);
+        chunk.write_op(Op::Return);
+    }
+    assert_eq!(
+        chunk,
+        Chunk::assemble(&chunk.disassembly("wide").to_string())?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn assemble_by_hand() -> Result<()> {
+    let chunk = Chunk::assemble(
+        "
+        ; adds two numbers
+        OP_CONSTANT 1.5
+        2 OP_CONSTANT 'hi' ; a comment
+        OP_NIL
+        OP_EQUAL
+        3 0004 OP_RETURN
+        ",
+    )?;
+    let mut expected = Chunk::new();
+    let a = expected.add_constant(Value::Number(1.5))?;
+    expected.write_op_arg(Op::Constant, a);
+    expected.set_location(Location::new(2, 0));
+    let b = expected.add_constant(Value::String("hi".into()))?;
+    expected.write_op_arg(Op::Constant, b);
+    expected.write_op(Op::Nil);
+    expected.write_op(Op::Equal);
+    expected.set_location(Location::new(3, 0));
+    expected.write_op(Op::Return);
+    assert_eq!(expected, chunk);
+
+    Ok(())
+}
+
+#[test]
+fn assemble_errors() {
+    for listing in [
+        "OP_BOGUS",
+        "OP_NIL 1",
+        "OP_CONSTANT",
+        "OP_CONSTANT 'open",
+        "OP_CONSTANT 'bad \\q escape'",
+        "OP_CONSTANT 0 1\nOP_CONSTANT 0 2",
+        "OP_CONSTANT 1 1",
+        "OP_CONSTANT 0 1\nOP_CONSTANT 2 2",
+        "OP_CONSTANT 4294967295 1",
+        "OP_EXTEND 1",
+        "OP_ADD",
+        "1 2",
+    ] {
+        assert!(Chunk::assemble(listing).is_err(), "accepted {:?}", listing);
+    }
+}
+
+#[test]
+fn disassemble_structured() -> Result<()> {
+    let mut chunk = Chunk::new();
+    for i in 0..257 {
+        chunk.add_constant(Value::Number(i as f64))?;
+    }
+    chunk.set_location(Location::new(3, 0));
+    chunk.write_op_arg(Op::Constant, 256);
+    chunk.set_location(Location::new(4, 0));
+    chunk.write_op(Op::Negate);
+
+    let listing = chunk.disassemble();
+    assert_eq!(2, listing.len());
+    assert_eq!(0, listing[0].offset);
+    assert_eq!(3, listing[0].line);
+    assert_eq!(Op::Constant, listing[0].opcode);
+    assert_eq!(Some(256), listing[0].operand);
+    assert_eq!(Some(Value::Number(256.0)), listing[0].constant);
+    assert_eq!(1, listing[0].extend_len);
+    assert_eq!(2, listing[1].offset);
+    assert_eq!(4, listing[1].line);
+    assert_eq!(None, listing[1].operand);
+    assert_eq!(listing[1], chunk.disassemble_at(2));
+
+    assert_eq!(
+        "== c ==\n   3 0000 OP_CONSTANT 00000256 256\n   4 0002 OP_NEGATE\n",
+        chunk.disassembly("c").to_string()
+    );
+
+    Ok(())
+}
+
+fn listing(chunk: &Chunk) -> Vec<String> {
+    chunk
+        .disassemble()
+        .iter()
+        .map(|inst| match &inst.constant {
+            Some(value) => format!("{} {}", inst.opcode, value),
+            None => inst.opcode.to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn fold_constants() {
+    let chunk = compile("-(1 + 2) * 3");
+    assert_eq!(vec!["OP_CONSTANT -9", "OP_RETURN"], listing(&chunk));
+    assert_eq!(1, chunk.constants.len());
+    assert_eq!(
+        vec!["OP_CONSTANT 7", "OP_RETURN"],
+        listing(&compile("1 + 2 * 3"))
+    );
+
+    assert_eq!(
+        vec!["OP_TRUE", "OP_RETURN"],
+        listing(&compile("!nil == (\"a\" + \"b\" == \"ab\")"))
+    );
+    assert_eq!(vec!["OP_FALSE", "OP_RETURN"], listing(&compile("1 >= 2")));
+    assert_eq!(
+        vec!["OP_CONSTANT inf", "OP_RETURN"],
+        listing(&compile("1 / 0"))
+    );
+}
+
+#[test]
+fn fold_keeps_runtime_errors() {
+    assert_eq!(
+        vec!["OP_CONSTANT str", "OP_NEGATE", "OP_RETURN"],
+        listing(&compile("-\"str\""))
+    );
+    // The loads differ with plain_bytecode, but either way the division
+    // is left for runtime.
+    let nan = listing(&compile("0 / 0"));
+    assert_eq!(["OP_DIVIDE", "OP_RETURN"], nan[nan.len() - 2..]);
+    // The literal overflows to infinity, and each result is NaN.
+    let huge = "9".repeat(400);
+    for source in [
+        format!("{huge} - {huge}"),
+        format!("{huge} * 0"),
+        format!("-{huge} + {huge}"),
+    ] {
+        let chunk = compile(&source);
+        assert!(!listing(&chunk).concat().contains("NaN"), "{}", source);
+        let text = chunk.disassembly("nan").to_string();
+        assert_eq!(chunk, Chunk::assemble(&text).unwrap());
+    }
+    assert_eq!(
+        vec!["OP_CONSTANT -3", "OP_CONSTANT a", "OP_LESS", "OP_RETURN"],
+        listing(&compile("-(1 + 2) < \"a\""))
+    );
+    assert_eq!(
+        vec![
+            "OP_NIL",
+            "OP_CONSTANT 5",
+            "OP_LESS",
+            "OP_CONSTANT 6",
+            "OP_EQUAL",
+            "OP_RETURN"
+        ],
+        listing(&compile("(nil < 5) == 2 * 3"))
+    );
+}
+
+fn peephole(listing_text: &str) -> Vec<String> {
+    listing(&Chunk::assemble(listing_text).unwrap().peephole())
+}
+
+#[test]
+fn peephole_fuses_comparisons() {
+    let args = "OP_CONSTANT 1\nOP_CONSTANT 2\n";
+    for (ops, fused) in [
+        ("OP_EQUAL\nOP_NOT", "OP_NOT_EQUAL"),
+        ("OP_LESS\nOP_NOT", "OP_GREATER_EQUAL"),
+        ("OP_GREATER\nOP_NOT", "OP_LESS_EQUAL"),
+        ("OP_EQUAL\nOP_NOT\nOP_NOT", "OP_EQUAL"),
+        ("OP_LESS\nOP_NOT\nOP_NOT", "OP_LESS"),
+    ] {
+        let listing_text = format!("{args}{ops}\nOP_RETURN");
+        assert_eq!(
+            vec!["OP_CONSTANT 1", "OP_CONSTANT 2", fused, "OP_RETURN"],
+            peephole(&listing_text)
+        );
+    }
+}
+
+#[test]
+fn peephole_not_not() {
+    assert_eq!(
+        vec!["OP_NIL", "OP_NOT", "OP_NOT", "OP_RETURN"],
+        peephole("OP_NIL\nOP_NOT\nOP_NOT\nOP_RETURN")
+    );
+    assert_eq!(
+        vec!["OP_NIL", "OP_NOT", "OP_RETURN"],
+        peephole("OP_NIL\nOP_NOT\nOP_NOT\nOP_NOT\nOP_RETURN")
+    );
+    assert_eq!(
+        vec!["OP_TRUE", "OP_RETURN"],
+        peephole("OP_TRUE\nOP_NOT\nOP_NOT\nOP_RETURN")
+    );
+}
+
+#[test]
+fn peephole_dead_code() -> Result<()> {
+    let chunk = Chunk::assemble(
+        "1 OP_CONSTANT 'a'\n2 OP_NEGATE\n3 OP_RETURN\n4 OP_NIL\n5 OP_RETURN",
+    )?
+    .peephole();
+    assert_eq!(
+        vec!["OP_CONSTANT a", "OP_NEGATE", "OP_RETURN"],
+        listing(&chunk)
+    );
+    let lines: Vec<u32> = chunk.disassemble().iter().map(|i| i.line).collect();
+    assert_eq!(vec![1, 2, 3], lines);
+
+    let fused = Chunk::assemble(
+        "1 OP_CONSTANT 'a'\n1 OP_NIL\n2 OP_LESS\n3 OP_NOT\n4 OP_RETURN",
+    )?
+    .peephole();
+    let lines: Vec<u32> = fused.disassemble().iter().map(|i| i.line).collect();
+    assert_eq!(vec![1, 1, 2, 4], lines);
+
+    Ok(())
+}
+
+#[test]
+fn compiled_comparisons() {
+    assert_eq!(
+        vec![
+            "OP_CONSTANT a",
+            "OP_NIL",
+            "OP_ADD",
+            "OP_CONSTANT 2",
+            "OP_GREATER_EQUAL",
+            "OP_RETURN"
+        ],
+        listing(&compile("\"a\" + nil >= 2"))
+    );
+}
+
+#[test]
+#[cfg(not(feature = "plain_bytecode"))]
+fn superinstructions() -> Result<()> {
+    assert_eq!(
+        vec!["OP_CONSTANT a", "OP_ADD_CONSTANT 3", "OP_RETURN"],
+        listing(&compile("\"a\" + (1 + 2)"))
+    );
+    assert_eq!(
+        vec![
+            "OP_CONSTANT a",
+            "OP_CONSTANT0",
+            "OP_LESS",
+            "OP_CONSTANT1",
+            "OP_ADD",
+            "OP_RETURN"
+        ],
+        listing(&compile("(\"a\" < 0) + 1"))
+    );
+    assert_eq!(
+        vec!["OP_NIL", "OP_ADD_CONSTANT -0", "OP_RETURN"],
+        listing(&compile("nil + -0"))
+    );
+
+    // Instructions on different lines are kept apart, so errors are
+    // reported on the same line with and without superinstructions.
+    assert_eq!(
+        vec!["OP_CONSTANT a", "OP_CONSTANT 2", "OP_ADD", "OP_RETURN"],
+        listing(&compile("\"a\" +\n2"))
+    );
+
+    let chunk = compile("\"a\" + 2");
+    let mut bytes = Vec::new();
+    chunk.write_to(&mut bytes)?;
+    assert_eq!(chunk, Chunk::read_from(&mut bytes.as_slice())?);
+    assert_eq!(1, chunk.max_stack());
+
+    Ok(())
+}
+
+#[test]
+fn constant_pool_dedup() -> Result<()> {
+    let mut chunk = Chunk::new();
+    for value in [
+        Value::Number(2.0),
+        Value::String("key".into()),
+        Value::Number(2.0),
+        Value::String("key".into()),
+        Value::Number(-0.0),
+        Value::Number(0.0),
+        Value::Number(f64::NAN),
+        Value::Number(f64::NAN),
+    ] {
+        chunk.add_constant(value)?;
+    }
+    assert_eq!(
+        PoolStats {
+            len: 5,
+            numbers: 4,
+            strings: 1,
+            string_bytes: 3,
+        },
+        chunk.pool_stats()
+    );
+
+    let listing = "OP_CONSTANT 'a'\nOP_NEGATE\nOP_CONSTANT 'a'\nOP_ADD\n\
+                   OP_CONSTANT 1 'b'\nOP_CONSTANT 'b'\nOP_ADD\nOP_RETURN";
+    let chunk = Chunk::assemble(listing)?;
+    assert_eq!(2, chunk.pool_stats().len);
+    assert_eq!(
+        vec![Some(0), Some(0), Some(1), Some(1)],
+        chunk
+            .disassemble()
+            .iter()
+            .filter(|inst| inst.opcode == Op::Constant)
+            .map(|inst| inst.operand)
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn line_map_matches_naive() {
+    let mut map = super::LineMap::new();
+    let mut naive = Vec::new();
+    let mut seed = 12345u32;
+    let mut location = Location::new(1, 0);
+    for _ in 0..2000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let r = seed >> 16;
+        match r % 4 {
+            0 => location.line += r % 3,
+            1 => location.line = location.line.saturating_sub(r % 5).max(1),
+            2 => location.column = r % 90,
+            _ => {}
+        }
+        map.set_location(location);
+        for _ in 0..=r % 3 {
+            map.add_op();
+            naive.push(location);
+        }
+    }
+
+    assert_eq!(naive.len(), map.len());
+    for (offset, &location) in naive.iter().enumerate() {
+        assert_eq!(location, map.get_location(offset), "offset {offset}");
+    }
+    assert!(map.heap_size() < naive.len() * std::mem::size_of::<u32>());
+}
+
+#[test]
+fn compiled_locations() -> Result<()> {
+    let chunk = compile("1 +\n  -\"a\"");
+    // The two constants, the negation, the addition and the return.
+    assert_eq!(
+        vec![(1, 1), (2, 4), (2, 3), (1, 3), (2, 7)],
+        chunk
+            .disassemble()
+            .iter()
+            .map(|inst| (inst.line, inst.column))
+            .collect::<Vec<_>>()
+    );
+
+    let listing = chunk.disassembly("c").to_string();
+    assert!(listing.contains(" 2:4 0001 OP_CONSTANT"), "{}", listing);
+    assert_eq!(chunk, Chunk::assemble(&listing)?);
+
+    Ok(())
+}
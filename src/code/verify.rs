@@ -0,0 +1,91 @@
+use num_enum::FromPrimitive;
+
+use super::{Chunk, Op};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum VerifyErrorKind {
+    #[error("unknown opcode {0:#04x}")]
+    UnknownOpcode(u8),
+    #[error("{0} does not take an operand")]
+    UnexpectedExtend(Op),
+    #[error("OP_EXTEND at end of code")]
+    DanglingExtend,
+    #[error("operand does not fit in 32 bits")]
+    OperandOverflow,
+    #[error("constant {index} out of range for pool of {len}")]
+    ConstantOutOfRange { index: u32, len: usize },
+    #[error("{0} pops from an empty stack")]
+    StackUnderflow(Op),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid bytecode at offset {offset:04}: {kind}")]
+pub struct VerifyError {
+    offset: usize,
+    kind: VerifyErrorKind,
+}
+
+impl VerifyError {
+    /// The offset of the first word of the offending instruction.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn kind(&self) -> VerifyErrorKind {
+        self.kind
+    }
+}
+
+impl Chunk {
+    /// Checks that the chunk can be run without trusting how it was
+    /// produced: every instruction decodes, operands are in range, and no
+    /// instruction pops more values than are on the stack.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let mut depth = 0;
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let start = offset;
+            let error = |kind| {
+                Err(VerifyError {
+                    offset: start,
+                    kind,
+                })
+            };
+
+            let mut operand = 0u32;
+            let op = loop {
+                let [byte, arg] = self.code[offset].to_be_bytes();
+                let op = Op::from_primitive(byte);
+                offset += 1;
+                if op == Op::Unknown {
+                    return error(VerifyErrorKind::UnknownOpcode(byte));
+                }
+                operand = match operand.checked_mul(0x100) {
+                    Some(v) => v | arg as u32,
+                    None => return error(VerifyErrorKind::OperandOverflow),
+                };
+                if op != Op::Extend {
+                    break op;
+                }
+                if offset == self.code.len() {
+                    return error(VerifyErrorKind::DanglingExtend);
+                }
+            };
+
+            if offset - start > 1 && op < Op::Constant {
+                return error(VerifyErrorKind::UnexpectedExtend(op));
+            }
+            if op.uses_constant() && operand as usize >= self.constants.len() {
+                return error(VerifyErrorKind::ConstantOutOfRange {
+                    index: operand,
+                    len: self.constants.len(),
+                });
+            }
+            if op.pops() > depth {
+                return error(VerifyErrorKind::StackUnderflow(op));
+            }
+            depth = (depth as isize + op.stack_effect()) as usize;
+        }
+        Ok(())
+    }
+}
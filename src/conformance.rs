@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::scanner::{Scanner, TokenType};
+use crate::{Parser, Vm};
+
+/// What a script annotated in the style of the craftinginterpreters test
+/// suite expects to happen when it runs:
+///
+/// - `// expect: text` expects a line of output.
+/// - `// expect runtime error: message` expects the run to fail on that
+///   line.
+/// - `// Error at 'x': message` expects a compile error on that line, and
+///   `// [line N] Error...` one on line `N`, in the order they are
+///   reported.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    pub compile_errors: Vec<String>,
+    pub runtime_error: Option<(u32, String)>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Expectations {
+        let mut expected = Expectations::default();
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.set_keep_comments(true);
+        loop {
+            let token = match scanner.scan_token() {
+                Ok(token) if token.ty() == TokenType::Eof => break,
+                Ok(token) if token.ty() == TokenType::Comment => token,
+                _ => continue,
+            };
+            let line = token.line();
+            let Some(comment) = scanner.token_text(token).strip_prefix("// ")
+            else {
+                continue;
+            };
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expected.output.push(output.to_string());
+            } else if let Some(msg) =
+                comment.strip_prefix("expect runtime error: ")
+            {
+                expected.runtime_error = Some((line, msg.to_string()));
+            } else if comment.starts_with("Error") {
+                let error = format!("[line {}] {}", line, comment);
+                expected.compile_errors.push(error);
+            } else if comment.starts_with("[line ") {
+                expected.compile_errors.push(comment.to_string());
+            }
+        }
+        expected
+    }
+}
+
+/// Collects what a script's run printed.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `source` and returns every way it differed from its annotations,
+/// so an empty list means the script passed.
+pub fn check_script(source: &str) -> Vec<String> {
+    let expected = Expectations::parse(source);
+    let mut failures = Vec::new();
+    let mut vm = Vm::init();
+    let capture = Capture::default();
+    vm.set_output(capture.clone());
+
+    let mut parser = Parser::new(source.to_string());
+    parser.set_quiet(true);
+    let chunk = parser.parse(&mut vm);
+    let errors: Vec<String> =
+        parser.errors().iter().map(|e| e.to_string()).collect();
+    let mut errors = errors.iter();
+    for expected in &expected.compile_errors {
+        match errors.next() {
+            Some(error) if error == expected => {}
+            Some(error) => failures.push(format!(
+                "expected compile error '{}', got '{}'",
+                expected, error
+            )),
+            None => {
+                failures.push(format!("missing compile error: {}", expected))
+            }
+        }
+    }
+    for error in errors {
+        failures.push(format!("unexpected compile error: {}", error));
+    }
+
+    let result = match chunk {
+        Some(chunk) => vm.interpret_chunk(chunk),
+        None => Ok(()),
+    };
+    match (result, &expected.runtime_error) {
+        (Ok(()), None) => {}
+        (Ok(()), Some((line, msg))) => failures
+            .push(format!("expected runtime error on line {}: {}", line, msg)),
+        (Err(e), Some((line, msg)))
+            if e.line() == Some(*line) && e.message() == msg => {}
+        (Err(e), _) => {
+            failures.push(format!("unexpected runtime error: {}", e))
+        }
+    }
+
+    let output = String::from_utf8_lossy(&capture.0.borrow()).into_owned();
+    let mut lines = output.lines();
+    for expected in &expected.output {
+        match lines.next() {
+            Some(line) if line == expected => {}
+            Some(line) => failures.push(format!(
+                "expected output '{}', got '{}'",
+                expected, line
+            )),
+            None => failures.push(format!("missing output '{}'", expected)),
+        }
+    }
+    for line in lines {
+        failures.push(format!("unexpected output '{}'", line));
+    }
+    failures
+}
+
+/// Returns `path` if it is a file, or every `.lox` file under it, sorted.
+pub fn find_scripts(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut scripts = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "lox") {
+                scripts.push(path);
+            }
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod test;
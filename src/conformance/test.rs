@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+
+use super::{check_script, find_scripts, Expectations};
+
+#[test]
+fn parse_annotations() {
+    let expected = Expectations::parse(
+        "1 // expect: 1\n\
+         2 // expect runtime error: oops\n\
+         ) // Error at ')': expect expression\n\
+         // [line 7] Error at end: expect expression\n",
+    );
+    assert_eq!(vec!["1"], expected.output);
+    assert_eq!(Some((2, "oops".to_string())), expected.runtime_error);
+    assert_eq!(
+        vec![
+            "[line 3] Error at ')': expect expression",
+            "[line 7] Error at end: expect expression",
+        ],
+        expected.compile_errors
+    );
+
+    let expected =
+        Expectations::parse("\"// expect: a\" + \"b\" // expect: ab");
+    assert_eq!(vec!["ab"], expected.output);
+}
+
+#[test]
+fn mismatches() {
+    assert!(check_script("1 + 2 // expect: 3").is_empty());
+    assert_eq!(
+        vec!["expected output '4', got '3'"],
+        check_script("1 + 2 // expect: 4")
+    );
+    assert_eq!(vec!["unexpected output '3'"], check_script("1 + 2"));
+    assert_eq!(
+        vec![
+            "unexpected runtime error: [line 1] operand must be a number",
+            "missing output 'nil'",
+        ],
+        check_script("-nil // expect: nil")
+    );
+    assert_eq!(
+        vec!["unexpected compile error: [line 1] Error at end: expect expression"],
+        check_script("1 +")
+    );
+    assert_eq!(
+        vec![
+            "expected compile error '[line 1] Error at end: expect expression', \
+             got '[line 1] Error: unexpected character '@''",
+            "missing compile error: [line 2] Error at end: expect expression",
+        ],
+        check_script(
+            "@ // Error at end: expect expression\n\
+             // [line 2] Error at end: expect expression"
+        )
+    );
+}
+
+#[test]
+fn suite() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+    let scripts = find_scripts(&dir).unwrap();
+    assert!(!scripts.is_empty());
+    for path in scripts {
+        let source = fs::read_to_string(&path).unwrap();
+        let failures = check_script(&source);
+        assert!(failures.is_empty(), "{}: {:?}", path.display(), failures);
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::bail;
+
+use crate::scanner::{Scanner, TokenType};
+use crate::Result;
+
+const INDENT: &str = "    ";
+
+/// The first line of a script that is not to be formatted.
+const SKIP: &str = "// fmt: skip";
+
+/// Reformats Lox source, keeping its comments and where its lines break
+/// but normalizing everything else: one space around binary operators and
+/// after commas, none inside parentheses or after prefix operators, `{` at
+/// the end of its line, `}` on its own, a new line after every `;`, blocks
+/// indented by four spaces, continuation lines by four more, and at most
+/// one blank line in a row. A continuation line after a trailing comment
+/// keeps its indentation.
+///
+/// Source whose first line is `// fmt: skip` is returned as it is, even
+/// if it does not scan, for scripts kept in a particular layout.
+pub fn format_source(source: &str) -> Result<String> {
+    if source.lines().next().map(str::trim_end) == Some(SKIP) {
+        return Ok(source.to_string());
+    }
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.set_keep_comments(true);
+    let mut formatter = Formatter::new();
+    loop {
+        let token = match scanner.scan_token() {
+            Ok(token) => token,
+            Err(e) => bail!("[line {}] Error: {}", scanner.location().0, e),
+        };
+        let ty = token.ty();
+        if ty == TokenType::Eof {
+            break;
+        }
+        let mut text = scanner.token_text(token);
+        if ty == TokenType::Comment {
+            text = text.trim_end();
+        }
+        let start_line = token.line();
+        let end_line = start_line + text.matches('\n').count() as u32;
+        let line_start = token.start() + 1 - token.column() as usize;
+        let leading = &source[line_start..token.start()];
+        formatter.push(ty, text, leading, start_line, end_line);
+    }
+    Ok(formatter.finish())
+}
+
+struct Formatter {
+    out: String,
+    depth: usize,
+    parens: usize,
+    prev: Option<TokenType>,
+    last_line: u32,
+    /// Whether the last token, ignoring comments, was a prefix operator.
+    prefix: bool,
+    /// Whether the last token, ignoring comments, can end an operand, so
+    /// that a `-` after it is binary.
+    after_operand: bool,
+    /// Whether the next token starts a statement rather than continuing
+    /// one.
+    statement_start: bool,
+    /// Comments on lines of their own, held back to be indented like the
+    /// token after them, each with whether a blank line precedes it.
+    comments: Vec<(bool, String)>,
+    /// Whether the last token was a comment after code on its line.
+    trailing_comment: bool,
+}
+
+impl Formatter {
+    fn new() -> Self {
+        Formatter {
+            out: String::new(),
+            depth: 0,
+            parens: 0,
+            prev: None,
+            last_line: 1,
+            prefix: false,
+            after_operand: false,
+            statement_start: true,
+            comments: Vec::new(),
+            trailing_comment: false,
+        }
+    }
+
+    /// Adds a token, given the text before it on its line in the source.
+    fn push(
+        &mut self,
+        ty: TokenType,
+        text: &str,
+        leading: &str,
+        line: u32,
+        end_line: u32,
+    ) {
+        if ty == TokenType::RightBrace {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        if let Some(prev) = self.prev {
+            let gap = line - self.last_line;
+            if self.breaks_before(prev, ty, gap) {
+                let blank = gap > 1
+                    && prev != TokenType::LeftBrace
+                    && ty != TokenType::RightBrace;
+                if ty == TokenType::Comment {
+                    self.comments.push((blank, text.to_string()));
+                    self.trailing_comment = false;
+                    self.prev = Some(ty);
+                    self.last_line = end_line;
+                    return;
+                }
+                let indent = match ty {
+                    TokenType::RightBrace => self.depth,
+                    _ => self.depth + !self.statement_start as usize,
+                };
+                // Comments before a `}` belong to the block it closes.
+                let comment_indent = match ty {
+                    TokenType::RightBrace => indent + 1,
+                    _ => indent,
+                };
+                if self.trailing_comment
+                    && !self.statement_start
+                    && ty != TokenType::RightBrace
+                {
+                    // The line continues an expression whose comment
+                    // explains it, so it is left where the author put it.
+                    self.new_line(blank, 0);
+                    self.out.push_str(leading);
+                } else {
+                    self.flush_comments(comment_indent);
+                    self.new_line(blank, indent);
+                }
+            } else if self.spaced(prev, ty) {
+                self.out.push(' ');
+            }
+        }
+        self.out.push_str(text);
+
+        match ty {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => {
+                self.parens = self.parens.saturating_sub(1)
+            }
+            TokenType::LeftBrace => self.depth += 1,
+            _ => {}
+        }
+        if ty != TokenType::Comment {
+            self.prefix = ty == TokenType::Bang
+                || ty == TokenType::Minus && !self.after_operand;
+            self.after_operand = matches!(
+                ty,
+                TokenType::Number
+                    | TokenType::String
+                    | TokenType::Identifier
+                    | TokenType::True
+                    | TokenType::False
+                    | TokenType::Nil
+                    | TokenType::This
+                    | TokenType::Super
+                    | TokenType::RightParen
+            );
+            self.statement_start =
+                matches!(ty, TokenType::LeftBrace | TokenType::RightBrace)
+                    || ty == TokenType::Semicolon && self.parens == 0;
+        }
+        self.trailing_comment = ty == TokenType::Comment;
+        self.prev = Some(ty);
+        self.last_line = end_line;
+    }
+
+    /// Whether `ty` goes on a new line, given the number of line breaks
+    /// before it in the source.
+    fn breaks_before(&self, prev: TokenType, ty: TokenType, gap: u32) -> bool {
+        match (prev, ty) {
+            (TokenType::Comment, _) => true,
+            (_, TokenType::LeftBrace) => false,
+            (_, TokenType::RightBrace) => true,
+            (_, TokenType::Comment) => gap > 0,
+            (
+                TokenType::RightBrace,
+                TokenType::Else | TokenType::Semicolon | TokenType::Comma,
+            ) => false,
+            (TokenType::RightBrace | TokenType::LeftBrace, _) => true,
+            (TokenType::Semicolon, _) if self.parens == 0 => true,
+            (_, TokenType::Semicolon | TokenType::Comma) => false,
+            _ => gap > 0,
+        }
+    }
+
+    /// Whether a space separates `prev` and `ty` on the same line.
+    fn spaced(&self, prev: TokenType, ty: TokenType) -> bool {
+        match (prev, ty) {
+            // `- -a`, which would read as a decrement without the space.
+            (TokenType::Minus, TokenType::Minus) => true,
+            _ if self.prefix => false,
+            (TokenType::LeftParen | TokenType::Dot, _) => false,
+            (
+                _,
+                TokenType::RightParen
+                | TokenType::Comma
+                | TokenType::Semicolon
+                | TokenType::Dot,
+            ) => false,
+            // A call.
+            (
+                TokenType::Identifier | TokenType::RightParen,
+                TokenType::LeftParen,
+            ) => false,
+            _ => true,
+        }
+    }
+
+    fn new_line(&mut self, blank: bool, indent: usize) {
+        if blank {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+        for _ in 0..indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn flush_comments(&mut self, indent: usize) {
+        for (blank, text) in std::mem::take(&mut self.comments) {
+            self.new_line(blank, indent);
+            self.out.push_str(&text);
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_comments(self.depth);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,60 @@
+use super::format_source;
+
+fn format(source: &str) -> String {
+    let formatted = format_source(source).unwrap();
+    assert_eq!(formatted, format_source(&formatted).unwrap());
+    formatted
+}
+
+#[test]
+fn spacing() {
+    assert_eq!("1 + 2 * 3\n", format("1+2   *3"));
+    assert_eq!("-(1 - -2) >= !true\n", format("- ( 1--2 )>=! true"));
+    assert_eq!("\"a\" + \"b c\" == nil\n", format("  \"a\"+\"b c\"==nil  "));
+    assert_eq!("f(a, b.c)\n", format("f (a ,b . c)"));
+    assert_eq!("a = - -b;\n", format("a = - -b;"));
+    assert_eq!("!!a - -1\n", format("! !a--1"));
+    assert_eq!("", format("  \n\n"));
+}
+
+#[test]
+fn comments_and_lines() {
+    assert_eq!(
+        "// total\n1 + // first\n      2\n\n// done\n",
+        format("  // total  \n1+   // first\n      2\n\n\n\n// done")
+    );
+    assert_eq!("\"a\nb\" +\n    1\n", format("\"a\nb\"+\n1"));
+    assert_eq!("1 + // a\n\"b\"\n", format("1+ // a\n\"b\""));
+    assert_eq!("1 + // a\n    // b\n    2\n", format("1+ // a\n// b\n2"));
+}
+
+#[test]
+fn blocks() {
+    assert_eq!(
+        "if (a) {\n    print 1;\n\n    print 2;\n} else {\n    print 3;\n}\n",
+        format("if(a)\n{print 1;\n\n\nprint 2;}\nelse{ print 3; }")
+    );
+    assert_eq!(
+        "{\n    print 1;\n    // inside\n}\n// after\n",
+        format("{print 1;\n// inside\n}\n  // after")
+    );
+    assert_eq!(
+        "for (var i = 0; i < 3; i = i + 1) {\n    print i;\n}\n",
+        format("for(var i=0;i<3;i=i+1){print i;}")
+    );
+}
+
+#[test]
+fn scan_errors() {
+    let err = format_source("1 +\n\"a").unwrap_err();
+    assert_eq!("[line 2] Error: unterminated string", err.to_string());
+    let err = format_source("1 +\n\"a\nb\nc").unwrap_err();
+    assert_eq!("[line 2] Error: unterminated string", err.to_string());
+}
+
+#[test]
+fn skip() {
+    for source in ["// fmt: skip\n1+\n  2 \"", "// fmt: skip"] {
+        assert_eq!(source, format_source(source).unwrap());
+    }
+}
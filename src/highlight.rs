@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+use crate::scanner::{Scanner, TokenType};
+
+/// The kinds of token the highlighter styles. Identifiers, punctuation and
+/// anything that does not scan are left plain.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Category {
+    Keyword,
+    Literal,
+    Operator,
+    Comment,
+}
+
+impl Category {
+    fn of(ty: TokenType) -> Option<Category> {
+        let category = match ty {
+            TokenType::And
+            | TokenType::Class
+            | TokenType::Else
+            | TokenType::For
+            | TokenType::Fun
+            | TokenType::If
+            | TokenType::Or
+            | TokenType::Print
+            | TokenType::Return
+            | TokenType::Super
+            | TokenType::This
+            | TokenType::Var
+            | TokenType::While => Category::Keyword,
+            TokenType::Number
+            | TokenType::String
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil => Category::Literal,
+            TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Slash
+            | TokenType::Star
+            | TokenType::Bang
+            | TokenType::BangEqual
+            | TokenType::Equal
+            | TokenType::EqualEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => Category::Operator,
+            TokenType::Comment => Category::Comment,
+            _ => return None,
+        };
+        Some(category)
+    }
+
+    /// The CSS class `highlight_html` gives the category's tokens.
+    pub fn class(self) -> &'static str {
+        match self {
+            Category::Keyword => "keyword",
+            Category::Literal => "literal",
+            Category::Operator => "operator",
+            Category::Comment => "comment",
+        }
+    }
+
+    fn ansi_color(self) -> &'static str {
+        match self {
+            Category::Keyword => "\x1b[35m",
+            Category::Literal => "\x1b[32m",
+            Category::Operator => "\x1b[33m",
+            Category::Comment => "\x1b[90m",
+        }
+    }
+}
+
+/// Splits `source` into pieces that together reproduce it exactly, each
+/// with the category it is styled as. Text the scanner rejects, such as
+/// an unterminated string, is left plain.
+pub fn highlight(source: &str) -> Vec<(Option<Category>, &str)> {
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.set_keep_comments(true);
+    let mut pieces = Vec::new();
+    let mut end = 0;
+    loop {
+        let token = match scanner.scan_token() {
+            Ok(token) => token,
+            Err(_) => continue,
+        };
+        if token.ty() == TokenType::Eof {
+            break;
+        }
+        if token.start() > end {
+            pieces.push((None, &source[end..token.start()]));
+        }
+        pieces.push((
+            Category::of(token.ty()),
+            &source[token.start()..token.end()],
+        ));
+        end = token.end();
+    }
+    if end < source.len() {
+        pieces.push((None, &source[end..]));
+    }
+    pieces
+}
+
+/// Renders `source` with ANSI color escapes, for a terminal.
+pub fn highlight_ansi(source: &str) -> String {
+    let mut out = String::new();
+    for (category, text) in highlight(source) {
+        match category {
+            Some(category) => {
+                write!(out, "{}{}\x1b[0m", category.ansi_color(), text).unwrap()
+            }
+            None => out.push_str(text),
+        }
+    }
+    out
+}
+
+/// Renders `source` as an HTML `<pre>` block, with each styled token in a
+/// `<span>` whose class is its category's.
+pub fn highlight_html(source: &str) -> String {
+    let mut out = String::from("<pre class=\"lox\"><code>");
+    for (category, text) in highlight(source) {
+        match category {
+            Some(category) => {
+                write!(out, "<span class=\"{}\">", category.class()).unwrap();
+                escape_html(&mut out, text);
+                out.push_str("</span>");
+            }
+            None => escape_html(&mut out, text),
+        }
+    }
+    out.push_str("</code></pre>\n");
+    out
+}
+
+fn escape_html(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
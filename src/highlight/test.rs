@@ -0,0 +1,48 @@
+use super::{highlight, highlight_ansi, highlight_html, Category};
+
+#[test]
+fn categories() {
+    let pieces = highlight("!(nil == x) // why\n");
+    assert_eq!(
+        vec![
+            (Some(Category::Operator), "!"),
+            (None, "("),
+            (Some(Category::Literal), "nil"),
+            (None, " "),
+            (Some(Category::Operator), "=="),
+            (None, " "),
+            (None, "x"),
+            (None, ")"),
+            (None, " "),
+            (Some(Category::Comment), "// why"),
+            (None, "\n"),
+        ],
+        pieces
+    );
+}
+
+#[test]
+fn scan_errors_stay_plain() {
+    let source = "1 @ é + \"open\n";
+    let text: String = highlight(source).iter().map(|(_, t)| *t).collect();
+    assert_eq!(source, text);
+    assert_eq!(Some(&(None, " \"open\n")), highlight(source).last());
+}
+
+#[test]
+fn ansi() {
+    assert_eq!(
+        "\x1b[32m1\x1b[0m \x1b[33m+\x1b[0m \x1b[32m\"a\"\x1b[0m",
+        highlight_ansi("1 + \"a\"")
+    );
+}
+
+#[test]
+fn html() {
+    assert_eq!(
+        "<pre class=\"lox\"><code><span class=\"literal\">\"&lt;b&gt;\"</span> \
+         <span class=\"operator\">&gt;=</span> \
+         <span class=\"keyword\">this</span></code></pre>\n",
+        highlight_html("\"<b>\" >= this")
+    );
+}
@@ -0,0 +1,414 @@
+use std::fmt::Write;
+use std::str;
+
+use crate::vm::RuntimeError;
+use crate::Value;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// How deeply arrays and objects may nest, so that hostile input cannot
+/// overflow the stack of the recursive reader.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn parse(text: &str) -> Result<Json> {
+        let mut reader = Reader {
+            text: text.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let json = reader.value()?;
+        reader.skip_whitespace();
+        if reader.pos < reader.text.len() {
+            return Err(reader.error("unexpected trailing characters"));
+        }
+        Ok(json)
+    }
+
+    /// Looks up `key` if this is an object.
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => {
+                members.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            Json::Number(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn stringify(&self, indent: Option<usize>) -> Result<String> {
+        let mut out = String::new();
+        self.write(&mut out, indent, 0)?;
+        Ok(out)
+    }
+
+    fn write(
+        &self,
+        out: &mut String,
+        indent: Option<usize>,
+        depth: usize,
+    ) -> Result<()> {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(v) => write!(out, "{}", v).unwrap(),
+            Json::Number(v) => {
+                if !v.is_finite() {
+                    return Err(RuntimeError::new(format!(
+                        "cannot represent {} in JSON",
+                        v
+                    )));
+                }
+                write!(out, "{}", v).unwrap();
+            }
+            Json::String(v) => write_string(out, v),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent, depth + 1);
+                    item.write(out, indent, depth + 1)?;
+                }
+                if !items.is_empty() {
+                    newline(out, indent, depth);
+                }
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent, depth + 1);
+                    write_string(out, key);
+                    out.push(':');
+                    if indent.is_some() {
+                        out.push(' ');
+                    }
+                    value.write(out, indent, depth + 1)?;
+                }
+                if !members.is_empty() {
+                    newline(out, indent, depth);
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+fn newline(out: &mut String, indent: Option<usize>, depth: usize) {
+    if let Some(width) = indent {
+        out.push('\n');
+        out.extend(std::iter::repeat_n(' ', width * depth));
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Reader<'a> {
+    text: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, msg: &str) -> RuntimeError {
+        RuntimeError::new(format!(
+            "invalid JSON at offset {}: {}",
+            self.pos, msg
+        ))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => self.nested(Reader::array),
+            Some(b'{') => self.nested(Reader::object),
+            Some(c) if c == b'-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, json: Json) -> Result<Json> {
+        if self.text[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(json)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(c) if c.is_ascii_digit() => {
+                self.digits();
+            }
+            _ => return Err(self.error("expected a digit")),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        let text = str::from_utf8(&self.text[start..self.pos]).unwrap();
+        let value: f64 = text.parse().unwrap();
+        if !value.is_finite() {
+            return Err(self.error("number out of range"));
+        }
+        Ok(Json::Number(value))
+    }
+
+    fn hex_escape(&mut self) -> Result<u32> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .filter(|d| d.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|d| str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char> {
+        let high = self.hex_escape()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.text[self.pos..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let low = self.hex_escape()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let c = match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(c) => c,
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek();
+                    self.pos += 1;
+                    let c = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("invalid escape"));
+                        }
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                c if c < b' ' => {
+                    self.pos -= 1;
+                    return Err(self.error("control character in string"));
+                }
+                c => bytes.push(c),
+            }
+        }
+        // Escapes were encoded as UTF-8 and the input is a &str, so the
+        // bytes are always valid.
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    fn nested(
+        &mut self,
+        read: fn(&mut Reader<'a>) -> Result<Json>,
+    ) -> Result<Json> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let json = read(self);
+        self.depth -= 1;
+        json
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.peek() == Some(b',') {
+                self.pos += 1;
+            } else {
+                self.expect(b']')?;
+                return Ok(Json::Array(items));
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            if self.peek() == Some(b',') {
+                self.pos += 1;
+            } else {
+                self.expect(b'}')?;
+                return Ok(Json::Object(members));
+            }
+        }
+    }
+}
+
+impl Value {
+    /// Encodes the value as JSON, pretty-printed with `indent` spaces per
+    /// level if given. Fails for values JSON cannot represent, such as NaN.
+    pub fn to_json(&self, indent: Option<usize>) -> Result<String> {
+        let json = match self {
+            Value::Nil => Json::Null,
+            Value::Boolean(v) => Json::Bool(*v),
+            Value::Number(v) => Json::Number(*v),
+            Value::String(v) => Json::String(v.to_string()),
+        };
+        json.stringify(indent)
+    }
+
+    /// Decodes a JSON document into a value. Arrays and objects have no
+    /// Lox equivalent yet and are rejected.
+    pub fn from_json(text: &str) -> Result<Value> {
+        match Json::parse(text)? {
+            Json::Null => Ok(Value::Nil),
+            Json::Bool(v) => Ok(Value::Boolean(v)),
+            Json::Number(v) => Ok(Value::Number(v)),
+            Json::String(v) => Ok(Value::String(v.into())),
+            Json::Array(_) => Err(RuntimeError::new(
+                "cannot convert a JSON array to a value".to_string(),
+            )),
+            Json::Object(_) => Err(RuntimeError::new(
+                "cannot convert a JSON object to a value".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,93 @@
+use super::Json;
+use crate::vm::RuntimeError;
+use crate::Value;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+#[test]
+fn encode_values() -> Result<()> {
+    assert_eq!("null", Value::Nil.to_json(None)?);
+    assert_eq!("true", Value::Boolean(true).to_json(None)?);
+    assert_eq!("1.5", Value::Number(1.5).to_json(None)?);
+    assert_eq!("-3", Value::Number(-3.0).to_json(None)?);
+    assert_eq!(
+        r#""a \"quoted\"\n\ttab \u0001""#,
+        Value::String("a \"quoted\"\n\ttab \u{1}".into()).to_json(None)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn encode_unrepresentable() {
+    assert!(Value::Number(f64::NAN).to_json(None).is_err());
+    assert!(Value::Number(f64::INFINITY).to_json(None).is_err());
+}
+
+#[test]
+fn decode_values() -> Result<()> {
+    assert!(matches!(Value::from_json(" null ")?, Value::Nil));
+    assert!(matches!(Value::from_json("false")?, Value::Boolean(false)));
+    assert!(
+        matches!(Value::from_json("-1.25e2")?, Value::Number(v) if v == -125.0)
+    );
+    match Value::from_json(r#""caf\u00e9 \ud83d\ude00 \/""#)? {
+        Value::String(s) => assert_eq!("café 😀 /", &*s),
+        _ => panic!("expected a string"),
+    }
+    assert!(Value::from_json("[1, 2]").is_err());
+    assert!(Value::from_json(r#"{"a": 1}"#).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn decode_errors() {
+    for text in [
+        "",
+        "nul",
+        "01",
+        "1.",
+        "-",
+        "1e",
+        "\"abc",
+        "\"\\x\"",
+        "\"\\ud800\"",
+        "\"\\u+041\"",
+        "[1,]",
+        "{\"a\" 1}",
+        "1 2",
+        "\"\n\"",
+        "1e400",
+        "-1e400",
+    ] {
+        assert!(Json::parse(text).is_err(), "accepted {:?}", text);
+    }
+}
+
+#[test]
+fn round_trip_documents() -> Result<()> {
+    let text = r#"{"a":[1,true,null],"b":{},"c":[],"d":"x"}"#;
+    assert_eq!(text, Json::parse(text)?.stringify(None)?);
+
+    let pretty = Json::parse(text)?.stringify(Some(2))?;
+    assert_eq!(
+        "{\n  \"a\": [\n    1,\n    true,\n    null\n  ],\n  \"b\": {},\n  \
+         \"c\": [],\n  \"d\": \"x\"\n}",
+        pretty
+    );
+    assert_eq!(Json::parse(text)?, Json::parse(&pretty)?);
+
+    Ok(())
+}
+
+#[test]
+fn nesting_limit() -> Result<()> {
+    let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+    assert!(Json::parse(&nested(128)).is_ok());
+    let err = Json::parse(&nested(129)).unwrap_err();
+    assert!(err.to_string().contains("nested too deeply"), "{}", err);
+    assert!(Json::parse(&"[{\"a\":".repeat(100_000)).is_err());
+
+    Ok(())
+}
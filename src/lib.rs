@@ -1,16 +1,29 @@
 use std::{fmt, rc::Rc};
 
 pub use anyhow::Result;
-pub use parser::Parser;
-pub use vm::Vm;
+pub use code::{
+    Chunk, DisassembledInstruction, Disassembly, Op, PoolStats, VerifyError,
+    VerifyErrorKind,
+};
+pub use format::format_source;
+pub use highlight::{highlight, highlight_ansi, highlight_html, Category};
+pub use parser::{CompileError, Parser};
+pub use vm::{ErrorKind, InterruptHandle, MemoryStats, RuntimeError, Vm};
 
 mod code;
+pub mod conformance;
+mod format;
+mod highlight;
+mod json;
+pub mod lsp;
+#[cfg(any(test, feature = "nan_boxing"))]
+mod nanbox;
 mod parser;
 mod scanner;
 mod vm;
 
-#[derive(Clone, Default, PartialEq, PartialOrd)]
-pub(crate) enum Value {
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub enum Value {
     #[default]
     Nil,
     Boolean(bool),
@@ -19,8 +32,25 @@ pub(crate) enum Value {
 }
 
 impl Value {
-    const TRUE: Value = Value::Boolean(true);
-    const FALSE: Value = Value::Boolean(false);
+    #[cfg_attr(feature = "nan_boxing", allow(dead_code))]
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
 }
 
 impl fmt::Display for Value {
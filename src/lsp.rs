@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context};
+
+use crate::json::Json;
+use crate::scanner::{Scanner, TokenType};
+use crate::{Parser, Result, Vm};
+
+/// The semantic token types the server reports, in legend order.
+const TOKEN_TYPES: &[&str] = &[
+    "keyword", "number", "string", "operator", "comment", "variable",
+];
+
+/// The largest message body accepted, so that a bad header cannot make
+/// the server allocate without bound.
+const MAX_MESSAGE_LEN: usize = 64 << 20;
+
+// JSON-RPC error codes.
+const PARSE_ERROR: f64 = -32700.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_PARAMS: f64 = -32602.0;
+
+type Reply = std::result::Result<Json, (f64, String)>;
+
+/// Speaks the Language Server Protocol over `input` and `output` until
+/// the client sends `exit` or closes `input`. Documents are synced in
+/// full, and the server offers diagnostics, semantic tokens, hover and
+/// definitions.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                let reply = Err((PARSE_ERROR, e.to_string()));
+                write_message(&mut output, &response(&Json::Null, reply))?;
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Json::as_str);
+        let params = message.get("params").unwrap_or(&Json::Null);
+        match (method, message.get("id")) {
+            (Some("exit"), _) => break,
+            (Some(method), Some(id)) => {
+                let reply = server.request(method, params);
+                write_message(&mut output, &response(id, reply))?;
+            }
+            (Some(method), None) => {
+                for notification in server.notify(method, params) {
+                    write_message(&mut output, &notification)?;
+                }
+            }
+            // A response to a request we never send.
+            (None, _) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads the body of the next message, or `None` at the end of `input`.
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut length = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            match length {
+                None => return Ok(None),
+                Some(_) => bail!("unexpected end of input in headers"),
+            }
+        }
+        let line = header.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            bail!("invalid header '{}'", line);
+        };
+        if name.eq_ignore_ascii_case("Content-Length") {
+            let value =
+                value.trim().parse().context("invalid Content-Length")?;
+            length = Some(value);
+        }
+    }
+    let length = length.context("missing Content-Length header")?;
+    if length > MAX_MESSAGE_LEN {
+        bail!("message of {} bytes is too long", length);
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8(body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> Result<()> {
+    let body = message.stringify(None)?;
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn object<const N: usize>(members: [(&str, Json); N]) -> Json {
+    Json::Object(members.map(|(k, v)| (k.to_string(), v)).into())
+}
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn number(n: u32) -> Json {
+    Json::Number(n as f64)
+}
+
+fn response(id: &Json, reply: Reply) -> Json {
+    let (key, value) = match reply {
+        Ok(result) => ("result", result),
+        Err((code, message)) => (
+            "error",
+            object([
+                ("code", Json::Number(code)),
+                ("message", string(&message)),
+            ]),
+        ),
+    };
+    object([("jsonrpc", string("2.0")), ("id", id.clone()), (key, value)])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    object([
+        ("jsonrpc", string("2.0")),
+        ("method", string(method)),
+        ("params", params),
+    ])
+}
+
+fn range(line: u32, start: u32, end: u32) -> Json {
+    let position = |character| {
+        object([("line", number(line)), ("character", number(character))])
+    };
+    object([("start", position(start)), ("end", position(end))])
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>,
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Json) -> Reply {
+        match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => Ok(Json::Null),
+            "textDocument/semanticTokens/full" => {
+                let text = self.document(params)?;
+                Ok(object([("data", semantic_tokens(text))]))
+            }
+            "textDocument/hover" => {
+                let text = self.document(params)?;
+                let position = params.get("position");
+                let coordinate =
+                    |key| position.and_then(|p| p.get(key)?.as_number());
+                match (coordinate("line"), coordinate("character")) {
+                    (Some(line), Some(character)) => {
+                        Ok(hover(text, line as u32, character as u32))
+                    }
+                    _ => Err((INVALID_PARAMS, "missing position".to_string())),
+                }
+            }
+            // Scripts cannot declare anything yet, so no symbol has a
+            // definition to go to.
+            "textDocument/definition" => {
+                self.document(params)?;
+                Ok(Json::Null)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    /// Handles a notification, returning the notifications to send back.
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let Some(uri) = document_uri(params) else {
+            return Vec::new();
+        };
+        let text = match method {
+            "textDocument/didOpen" => {
+                params.get("textDocument").and_then(|d| d.get("text"))
+            }
+            // Only full syncs are offered, so the last change is the
+            // whole document.
+            "textDocument/didChange" => match params.get("contentChanges") {
+                Some(Json::Array(changes)) => {
+                    changes.last().and_then(|c| c.get("text"))
+                }
+                _ => None,
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Vec::new())];
+            }
+            _ => None,
+        };
+        match text.and_then(Json::as_str) {
+            Some(text) => {
+                self.documents.insert(uri.to_string(), text.to_string());
+                vec![publish_diagnostics(uri, diagnostics(text))]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn document(
+        &self,
+        params: &Json,
+    ) -> std::result::Result<&str, (f64, String)> {
+        let uri = document_uri(params)
+            .ok_or((INVALID_PARAMS, "missing textDocument".to_string()))?;
+        match self.documents.get(uri) {
+            Some(text) => Ok(text),
+            None => Err((INVALID_PARAMS, format!("unknown document {}", uri))),
+        }
+    }
+}
+
+fn document_uri(params: &Json) -> Option<&str> {
+    params.get("textDocument")?.get("uri")?.as_str()
+}
+
+fn capabilities() -> Json {
+    let token_types = TOKEN_TYPES.iter().map(|t| string(t)).collect();
+    let legend = object([
+        ("tokenTypes", Json::Array(token_types)),
+        ("tokenModifiers", Json::Array(Vec::new())),
+    ]);
+    object([
+        (
+            "capabilities",
+            object([
+                // Full document sync.
+                ("textDocumentSync", number(1)),
+                ("hoverProvider", Json::Bool(true)),
+                ("definitionProvider", Json::Bool(true)),
+                (
+                    "semanticTokensProvider",
+                    object([("legend", legend), ("full", Json::Bool(true))]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            object([
+                ("name", string("rlox")),
+                ("version", string(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+/// The length of `s` in UTF-16 code units, which LSP positions count in.
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+fn diagnostics(text: &str) -> Vec<Json> {
+    let mut parser = Parser::new(text.to_string());
+    parser.set_quiet(true);
+    parser.parse(&mut Vm::init());
+
+    let lines: Vec<&str> = text.split('\n').collect();
+    parser
+        .errors()
+        .iter()
+        .map(|error| {
+            let line = error.line() - 1;
+            let line_text = lines.get(line as usize).copied().unwrap_or("");
+            let column = (error.column() as usize - 1).min(line_text.len());
+            let start = utf16_len(&line_text[..column]);
+            let width = line_text[column..]
+                .chars()
+                .next()
+                .map_or(0, |c| c.len_utf16() as u32);
+            object([
+                ("range", range(line, start, start + width)),
+                // Error.
+                ("severity", number(1)),
+                ("source", string("rlox")),
+                ("message", string(error.message())),
+            ])
+        })
+        .collect()
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    notification(
+        "textDocument/publishDiagnostics",
+        object([
+            ("uri", string(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]),
+    )
+}
+
+/// A token's extent on one line, in UTF-16 code units from 0.
+struct Span<'a> {
+    line: u32,
+    start: u32,
+    len: u32,
+    ty: TokenType,
+    text: &'a str,
+}
+
+/// Splits `text` into the spans of its tokens, stopping at the first scan
+/// error. Strings spanning lines are split into a span per line.
+fn spans(text: &str) -> Vec<Span<'_>> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut scanner = Scanner::new(text.to_string());
+    scanner.set_keep_comments(true);
+    let mut spans = Vec::new();
+    while let Ok(token) = scanner.scan_token() {
+        if token.ty() == TokenType::Eof {
+            break;
+        }
+        let token_text = &text[token.start()..token.end()];
+        let first_line = token.line() - 1;
+        let first_column = token.column() as usize - 1;
+        for (i, piece) in token_text.split('\n').enumerate() {
+            let line = first_line + i as u32;
+            let column = if i == 0 { first_column } else { 0 };
+            spans.push(Span {
+                line,
+                start: utf16_len(&lines[line as usize][..column]),
+                len: utf16_len(piece),
+                ty: token.ty(),
+                text: piece,
+            });
+        }
+    }
+    spans
+}
+
+/// The index in `TOKEN_TYPES` that `ty` is highlighted as, if any.
+fn token_type(ty: TokenType) -> Option<usize> {
+    match ty {
+        TokenType::And
+        | TokenType::Class
+        | TokenType::Else
+        | TokenType::False
+        | TokenType::For
+        | TokenType::Fun
+        | TokenType::If
+        | TokenType::Nil
+        | TokenType::Or
+        | TokenType::Print
+        | TokenType::Return
+        | TokenType::Super
+        | TokenType::This
+        | TokenType::True
+        | TokenType::Var
+        | TokenType::While => Some(0),
+        TokenType::Number => Some(1),
+        TokenType::String => Some(2),
+        TokenType::Minus
+        | TokenType::Plus
+        | TokenType::Slash
+        | TokenType::Star
+        | TokenType::Bang
+        | TokenType::BangEqual
+        | TokenType::Equal
+        | TokenType::EqualEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Less
+        | TokenType::LessEqual => Some(3),
+        TokenType::Comment => Some(4),
+        TokenType::Identifier => Some(5),
+        _ => None,
+    }
+}
+
+/// Encodes the document's tokens as LSP's relative five-integer groups.
+fn semantic_tokens(text: &str) -> Json {
+    let mut data = Vec::new();
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for span in spans(text) {
+        let Some(ty) = token_type(span.ty) else {
+            continue;
+        };
+        if span.len == 0 {
+            continue;
+        }
+        let delta_start = match span.line == prev_line {
+            true => span.start - prev_start,
+            false => span.start,
+        };
+        data.extend([
+            span.line - prev_line,
+            delta_start,
+            span.len,
+            ty as u32,
+            0,
+        ]);
+        (prev_line, prev_start) = (span.line, span.start);
+    }
+    Json::Array(data.into_iter().map(number).collect())
+}
+
+fn describe(ty: TokenType) -> Option<&'static str> {
+    let description = match ty {
+        TokenType::Number => "number",
+        TokenType::String => "string",
+        TokenType::True | TokenType::False => "boolean",
+        TokenType::Nil => "nil",
+        TokenType::Minus => "subtraction or negation",
+        TokenType::Plus => "addition or string concatenation",
+        TokenType::Star => "multiplication",
+        TokenType::Slash => "division",
+        TokenType::Bang => "logical not",
+        TokenType::EqualEqual => "equality",
+        TokenType::BangEqual => "inequality",
+        TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Less
+        | TokenType::LessEqual => "comparison",
+        _ => return None,
+    };
+    Some(description)
+}
+
+/// Describes the token at a position, or returns null if there is none.
+fn hover(text: &str, line: u32, character: u32) -> Json {
+    let span = spans(text).into_iter().find(|span| {
+        span.line == line
+            && (span.start..span.start + span.len).contains(&character)
+    });
+    let Some(span) = span else {
+        return Json::Null;
+    };
+    let Some(description) = describe(span.ty) else {
+        return Json::Null;
+    };
+    object([
+        (
+            "contents",
+            object([
+                ("kind", string("markdown")),
+                (
+                    "value",
+                    string(&format!("`{}`: {}", span.text, description)),
+                ),
+            ]),
+        ),
+        ("range", range(span.line, span.start, span.start + span.len)),
+    ])
+}
+
+#[cfg(test)]
+mod test;
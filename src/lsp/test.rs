@@ -0,0 +1,171 @@
+use super::serve;
+use crate::json::Json;
+
+/// Sends `messages` to a server and returns everything it sent back.
+fn exchange(messages: &[&str]) -> Vec<Json> {
+    let mut input = String::new();
+    for message in messages {
+        input +=
+            &format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+    }
+    let mut output = Vec::new();
+    serve(input.as_bytes(), &mut output).unwrap();
+
+    let mut output = String::from_utf8(output).unwrap();
+    let mut replies = Vec::new();
+    while !output.is_empty() {
+        let (header, rest) = output.split_once("\r\n\r\n").unwrap();
+        let length: usize = header
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        replies.push(Json::parse(&rest[..length]).unwrap());
+        output = rest[length..].to_string();
+    }
+    replies
+}
+
+fn open(text: &str) -> String {
+    let text = Json::String(text.to_string()).stringify(None).unwrap();
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.lox","languageId":"lox","version":1,"text":{}}}}}}}"#,
+        text
+    )
+}
+
+fn stringify(json: &Json) -> String {
+    json.stringify(None).unwrap()
+}
+
+#[test]
+fn lifecycle() {
+    let replies = exchange(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+    ]);
+    assert_eq!(2, replies.len());
+    let capabilities = replies[0].get("result").unwrap().get("capabilities");
+    for provider in ["hoverProvider", "definitionProvider"] {
+        assert_eq!(
+            Some(&Json::Bool(true)),
+            capabilities.unwrap().get(provider)
+        );
+    }
+    assert_eq!(
+        r#"{"jsonrpc":"2.0","id":2,"result":null}"#,
+        stringify(&replies[1])
+    );
+}
+
+#[test]
+fn errors() {
+    let replies = exchange(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"nope"}"#,
+        r#"{"jsonrpc":"2.0","id":2,"#,
+    ]);
+    assert_eq!(
+        r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"unknown method nope"}}"#,
+        stringify(&replies[0])
+    );
+    let error = replies[1].get("error").unwrap();
+    assert_eq!(Some(-32700.0), error.get("code").unwrap().as_number());
+}
+
+#[test]
+fn message_too_long() {
+    let input = "Content-Length: 1000000000000\r\n\r\n{}";
+    let err = serve(input.as_bytes(), Vec::new()).unwrap_err();
+    assert_eq!(
+        "message of 1000000000000 bytes is too long",
+        err.to_string()
+    );
+}
+
+#[test]
+fn diagnostics() {
+    let replies = exchange(&[
+        &open("1 +\n  \"é\" )"),
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.lox","version":2},"contentChanges":[{"text":"1 + 2"}]}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didClose","params":{"textDocument":{"uri":"file:///a.lox"}}}"#,
+    ]);
+    assert_eq!(
+        r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.lox","diagnostics":[{"range":{"start":{"line":1,"character":6},"end":{"line":1,"character":7}},"severity":1,"source":"rlox","message":"expect end of expression"}]}}"#,
+        stringify(&replies[0])
+    );
+    for reply in &replies[1..] {
+        let params = reply.get("params").unwrap();
+        assert_eq!(Some(&Json::Array(Vec::new())), params.get("diagnostics"));
+    }
+}
+
+#[test]
+fn semantic_tokens() {
+    let replies = exchange(&[
+        &open("(1 + \"a\nb\") // c"),
+        r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/semanticTokens/full","params":{"textDocument":{"uri":"file:///a.lox"}}}"#,
+    ]);
+    let data = replies[1].get("result").unwrap().get("data").unwrap();
+    assert_eq!(
+        "[0,1,1,1,0,0,2,1,3,0,0,2,2,2,0,1,0,2,2,0,0,4,4,4,0]",
+        stringify(data)
+    );
+}
+
+#[test]
+fn hover() {
+    let hover = |character| {
+        let request = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{{"textDocument":{{"uri":"file:///a.lox"}},"position":{{"line":0,"character":{}}}}}}}"#,
+            character
+        );
+        let replies = exchange(&[&open("\"é\" + nil"), &request]);
+        stringify(replies[1].get("result").unwrap())
+    };
+    assert_eq!(
+        r#"{"contents":{"kind":"markdown","value":"`+`: addition or string concatenation"},"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}"#,
+        hover(4)
+    );
+    assert!(hover(0).contains(r#""value":"`\"é\"`: string""#));
+    assert_eq!("null", hover(3));
+}
+
+#[test]
+fn scan_error_diagnostics() {
+    let replies = exchange(&[&open("1 + é"), &open("1 +\n  \"a")]);
+    assert_eq!(
+        r#"[{"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}},"severity":1,"source":"rlox","message":"unexpected character 'é'"}]"#,
+        stringify(
+            replies[0]
+                .get("params")
+                .unwrap()
+                .get("diagnostics")
+                .unwrap()
+        )
+    );
+    assert_eq!(
+        r#"[{"range":{"start":{"line":1,"character":2},"end":{"line":1,"character":3}},"severity":1,"source":"rlox","message":"unterminated string"}]"#,
+        stringify(
+            replies[1]
+                .get("params")
+                .unwrap()
+                .get("diagnostics")
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn definition() {
+    let request = r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.lox"},"position":{"line":0,"character":0}}}"#;
+    let replies = exchange(&[&open("1 + 2"), request]);
+    assert_eq!(
+        r#"{"jsonrpc":"2.0","id":1,"result":null}"#,
+        stringify(&replies[1])
+    );
+    let replies = exchange(&[request]);
+    assert!(replies[0].get("error").is_some());
+}
@@ -1,47 +1,385 @@
+use anyhow::Context;
 use std::env;
-use std::io::{stdin, stdout, BufRead, Write};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, stdin, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
-use rlox::{Result, Vm};
+use rlox::conformance::{check_script, find_scripts};
+use rlox::{
+    format_source, highlight_ansi, highlight_html, Chunk, Parser, Result, Vm,
+};
 
-fn main() -> Result<()> {
+mod repl;
+
+// Exit codes from sysexits.h.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
+const EX_IOERR: i32 = 74;
+const EX_PROTOCOL: i32 = 76;
+
+/// `rlox test` or `rlox fmt --check` found scripts that fail the check.
+const EX_CHECKFAIL: i32 = 1;
+
+/// The outcome of a command: `Err` holds the exit code of a failure that
+/// has already been reported.
+type Status = std::result::Result<(), i32>;
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Some(options) => options,
+        None => usage(),
+    };
     let mut vm = Vm::init();
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        1 => repl(&mut vm)?,
-        2 => {
-            let source = std::fs::read_to_string(&args[1])?;
-            vm.interpret(source)?;
+    vm.set_trace(options.trace);
+    vm.set_args(options.args.clone());
+    if let Err(code) = options.run(&mut vm) {
+        exit(code);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: rlox [--trace] [<input> [<arg>...]]");
+    eprintln!("       rlox run [--trace] <input> [<arg>...]");
+    eprintln!("       rlox check <input>");
+    eprintln!("       rlox tokens <input>");
+    eprintln!("       rlox disasm <input>");
+    eprintln!("       rlox compile <input> [-o <output>]");
+    eprintln!("       rlox test <path>");
+    eprintln!("       rlox fmt [--check] <input>");
+    eprintln!("       rlox highlight [--html] <input>");
+    eprintln!("       rlox lsp");
+    eprintln!();
+    eprintln!("An input is a source or compiled file, - for stdin, or");
+    eprintln!("-e <code>. Without one, rlox starts a REPL. Arguments after");
+    eprintln!("the input are passed to the script.");
+    exit(EX_USAGE);
+}
+
+/// Reports an error and returns the exit code for it.
+fn fail(code: i32, msg: impl Display) -> i32 {
+    eprintln!("rlox: {}", msg);
+    code
+}
+
+#[derive(Default)]
+struct Options {
+    command: Option<String>,
+    trace: bool,
+    check: bool,
+    html: bool,
+    eval: Option<String>,
+    output: Option<PathBuf>,
+    path: Option<String>,
+    /// Arguments passed through to the script.
+    args: Vec<String>,
+}
+
+impl Options {
+    const COMMANDS: &'static [&'static str] = &[
+        "run",
+        "check",
+        "tokens",
+        "disasm",
+        "compile",
+        "test",
+        "fmt",
+        "lsp",
+        "highlight",
+    ];
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Options> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            // Everything after the script's input belongs to the script.
+            if options.runs_script()
+                && (options.eval.is_some() || options.path.is_some())
+            {
+                options.args.push(arg);
+                options.args.extend(args.by_ref());
+                break;
+            }
+            match arg.as_str() {
+                "--trace" => options.trace = true,
+                "--check" => options.check = true,
+                "--html" => options.html = true,
+                "-e" => options.eval = Some(args.next()?),
+                "-o" => options.output = Some(args.next()?.into()),
+                "--" if options.path.is_none() => {
+                    options.path = Some(args.next()?)
+                }
+                "--" => return None,
+                flag if flag.starts_with('-') && flag != "-" => return None,
+                command
+                    if options.command.is_none()
+                        && options.path.is_none()
+                        && Options::COMMANDS.contains(&command) =>
+                {
+                    options.command = Some(arg)
+                }
+                _ if options.path.is_none() => options.path = Some(arg),
+                _ => return None,
+            }
         }
-        _ => {
-            eprintln!("Usage: rlox [path]");
-            exit(1);
+        Some(options)
+    }
+
+    fn runs_script(&self) -> bool {
+        matches!(self.command.as_deref(), None | Some("run"))
+    }
+
+    fn run(&self, vm: &mut Vm) -> Status {
+        let command = self.command.as_deref().unwrap_or("run");
+        if self.output.is_some() && command != "compile"
+            || self.trace && command != "run"
+            || self.check && command != "fmt"
+            || self.html && command != "highlight"
+        {
+            usage();
+        }
+        if command == "lsp" {
+            if self.eval.is_some() || self.path.is_some() {
+                usage();
+            }
+            return rlox::lsp::serve(stdin().lock(), io::stdout().lock())
+                .map_err(|e| fail(EX_PROTOCOL, e));
+        }
+        if command == "test" {
+            return match (&self.eval, &self.path) {
+                (None, Some(path)) => run_tests(Path::new(path)),
+                _ => usage(),
+            };
+        }
+        if command == "fmt" {
+            return match (&self.eval, &self.path) {
+                (Some(code), None) => {
+                    format_stdout(code, "<script>", self.check)
+                }
+                (None, Some(path)) if path == "-" => {
+                    let mut source = String::new();
+                    stdin()
+                        .read_to_string(&mut source)
+                        .map_err(|e| fail(EX_IOERR, e))?;
+                    format_stdout(&source, "<stdin>", self.check)
+                }
+                (None, Some(path)) => format_files(Path::new(path), self.check),
+                _ => usage(),
+            };
+        }
+        let program = match (&self.eval, &self.path) {
+            (Some(code), None) => Program::Source(code.clone()),
+            (None, Some(path)) => {
+                let bytes = read_input(path)
+                    .map_err(|e| fail(EX_NOINPUT, format!("{:#}", e)))?;
+                decode_program(path, bytes)
+                    .map_err(|e| fail(EX_DATAERR, format!("{:#}", e)))?
+            }
+            (None, None) if self.command.is_none() => {
+                return repl::run(vm).map_err(|e| fail(EX_IOERR, e));
+            }
+            _ => usage(),
+        };
+
+        match command {
+            "run" => {
+                let chunk = program.compile(vm)?;
+                vm.interpret_chunk(chunk).map_err(|e| {
+                    eprintln!("{}", e);
+                    EX_SOFTWARE
+                })
+            }
+            "check" => program.compile(vm).map(drop),
+            "highlight" => match program {
+                Program::Source(source) if self.html => {
+                    print!("{}", highlight_html(&source));
+                    Ok(())
+                }
+                Program::Source(source) => {
+                    print!("{}", highlight_ansi(&source));
+                    if !source.ends_with('\n') {
+                        println!();
+                    }
+                    Ok(())
+                }
+                Program::Compiled(_) => {
+                    Err(fail(EX_DATAERR, "cannot highlight a compiled chunk"))
+                }
+            },
+            "tokens" => match program {
+                Program::Source(source) => {
+                    match Parser::new(source).show_tokens() {
+                        true => Ok(()),
+                        false => Err(EX_DATAERR),
+                    }
+                }
+                Program::Compiled(_) => {
+                    Err(fail(EX_DATAERR, "cannot scan a compiled chunk"))
+                }
+            },
+            "disasm" => {
+                let name = self.path.as_deref().unwrap_or("<script>");
+                print!("{}", program.compile(vm)?.disassembly(name));
+                Ok(())
+            }
+            "compile" => {
+                let output = match (&self.output, &self.path) {
+                    (Some(output), _) => output.clone(),
+                    (None, Some(path)) if path != "-" => {
+                        PathBuf::from(path).with_extension("loxc")
+                    }
+                    _ => usage(),
+                };
+                let chunk = program.compile(vm)?;
+                write_chunk(&chunk, &output)
+            }
+            _ => unreachable!(),
         }
     }
+}
+
+enum Program {
+    Source(String),
+    Compiled(Chunk),
+}
+
+impl Program {
+    /// Compiles the program, reporting any errors.
+    fn compile(self, vm: &mut Vm) -> std::result::Result<Chunk, i32> {
+        match self {
+            Program::Source(source) => {
+                Parser::new(source).parse(vm).ok_or(EX_DATAERR)
+            }
+            Program::Compiled(chunk) => Ok(chunk),
+        }
+    }
+}
+
+/// Reads a source or compiled file, or stdin if `path` is `-`.
+fn read_program(path: &str) -> Result<Program> {
+    decode_program(path, read_input(path)?)
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if path == "-" {
+        stdin().read_to_end(&mut bytes)?;
+    } else {
+        bytes = fs::read(path).with_context(|| path.to_string())?;
+    }
+    Ok(bytes)
+}
+
+/// Loads the contents of a file as a compiled chunk if it starts with the
+/// chunk header, and as source otherwise.
+fn decode_program(path: &str, bytes: Vec<u8>) -> Result<Program> {
+    if bytes.starts_with(Chunk::MAGIC) {
+        let chunk = Chunk::read_from(&mut bytes.as_slice())
+            .with_context(|| path.to_string())?;
+        Ok(Program::Compiled(chunk))
+    } else {
+        let source =
+            String::from_utf8(bytes).with_context(|| path.to_string())?;
+        Ok(Program::Source(source))
+    }
+}
+
+/// Checks each `.lox` script under `path` against its `// expect:`
+/// annotations.
+fn run_tests(path: &Path) -> Status {
+    let scripts = find_scripts(path)
+        .map_err(|e| fail(EX_NOINPUT, format!("{}: {}", path.display(), e)))?;
+    let mut failed = 0;
+    for script in &scripts {
+        let source = fs::read_to_string(script).map_err(|e| {
+            fail(EX_NOINPUT, format!("{}: {}", script.display(), e))
+        })?;
+        let failures = check_script(&source);
+        if !failures.is_empty() {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for failure in failures {
+                println!("     {}", failure);
+            }
+        }
+    }
+    println!("{} passed, {} failed", scripts.len() - failed, failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(EX_CHECKFAIL),
+    }
+}
+
+/// Prints `source` formatted, or with `--check` prints `name` if it is
+/// not formatted.
+fn format_stdout(source: &str, name: &str, check: bool) -> Status {
+    let formatted = format_source(source).map_err(|e| fail(EX_DATAERR, e))?;
+    match check {
+        false => print!("{}", formatted),
+        true if formatted != source => {
+            println!("{}", name);
+            return Err(EX_CHECKFAIL);
+        }
+        true => {}
+    }
     Ok(())
 }
 
-fn repl(vm: &mut Vm) -> Result<()> {
-    let mut lines = stdin().lock().lines();
-    let mut line_no = 1;
-    let mut source: Vec<String> = Vec::new();
-    loop {
-        print!("{:4}> ", line_no);
-        stdout().flush()?;
-        let mut line = match lines.next() {
-            None => break,
-            Some(line) => line?,
+/// Formats each `.lox` script under `path` in place, or with `--check`
+/// lists those that are not formatted. Scripts that cannot be scanned are
+/// reported and left alone.
+fn format_files(path: &Path, check: bool) -> Status {
+    let scripts = find_scripts(path)
+        .map_err(|e| fail(EX_NOINPUT, format!("{}: {}", path.display(), e)))?;
+    let mut unformatted = 0;
+    let mut status = Ok(());
+    for script in &scripts {
+        let source = fs::read_to_string(script).map_err(|e| {
+            fail(EX_NOINPUT, format!("{}: {}", script.display(), e))
+        })?;
+        let formatted = match format_source(&source) {
+            Ok(formatted) if formatted == source => continue,
+            Ok(formatted) => formatted,
+            Err(e) => {
+                let error = format!("{}: {}", script.display(), e);
+                status = Err(fail(EX_DATAERR, error));
+                continue;
+            }
         };
-        line_no += 1;
-        if line.ends_with('\\') {
-            line.pop();
-            source.push(line);
-            continue;
+        unformatted += 1;
+        if check {
+            println!("{}", script.display());
         } else {
-            source.push(line);
-            vm.interpret(source.join("\n"))?;
-            source.clear();
+            fs::write(script, formatted).map_err(|e| {
+                fail(EX_IOERR, format!("{}: {}", script.display(), e))
+            })?;
         }
     }
+    match unformatted {
+        n if check && n > 0 && status.is_ok() => Err(EX_CHECKFAIL),
+        _ => status,
+    }
+}
+
+/// Runs a file for the REPL's `:load` command.
+fn run_file(vm: &mut Vm, path: &str) -> Result<()> {
+    match read_program(path)? {
+        Program::Source(source) => vm.interpret(source)?,
+        Program::Compiled(chunk) => vm.interpret_chunk(chunk)?,
+    }
     Ok(())
 }
+
+fn write_chunk(chunk: &Chunk, output: &PathBuf) -> Status {
+    let file = File::create(output).map_err(|e| {
+        fail(EX_CANTCREAT, format!("{}: {}", output.display(), e))
+    })?;
+    let mut file = BufWriter::new(file);
+    chunk
+        .write_to(&mut file)
+        .and_then(|()| Ok(file.flush()?))
+        .map_err(|e| fail(EX_IOERR, format!("{}: {}", output.display(), e)))
+}
@@ -0,0 +1,181 @@
+//! An 8-byte encoding of `Value` for the VM stack.
+//!
+//! Numbers are stored as their IEEE bits. Every other value is a quiet NaN
+//! whose low bits hold a tag (nil, false, true) or, with the sign bit set,
+//! a pointer to the string. NaN results are canonicalized when boxed so
+//! they can never be mistaken for a tagged value.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::Value;
+
+#[cfg(not(target_pointer_width = "64"))]
+compile_error!("the nan_boxing feature requires 64-bit pointers");
+
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const STRING: u64 = SIGN_BIT | QNAN;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+pub(crate) struct NanBox {
+    bits: u64,
+    // Strings are reference counted without synchronization.
+    _rc: PhantomData<Rc<str>>,
+}
+
+impl NanBox {
+    const NIL: NanBox = NanBox::from_bits(QNAN | TAG_NIL);
+    const FALSE: NanBox = NanBox::from_bits(QNAN | TAG_FALSE);
+    const TRUE: NanBox = NanBox::from_bits(QNAN | TAG_TRUE);
+
+    const fn from_bits(bits: u64) -> Self {
+        NanBox {
+            bits,
+            _rc: PhantomData,
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        self.bits & QNAN != QNAN
+    }
+
+    fn is_string(&self) -> bool {
+        self.bits & STRING == STRING
+    }
+
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        self.is_number().then(|| f64::from_bits(self.bits))
+    }
+
+    fn string_ptr(&self) -> *const Rc<str> {
+        (self.bits & !STRING) as *const Rc<str>
+    }
+
+    fn as_string(&self) -> Option<&Rc<str>> {
+        // SAFETY: a string box owns one strong reference to the pointee,
+        // which therefore lives at least as long as `self`.
+        self.is_string().then(|| unsafe { &*self.string_ptr() })
+    }
+}
+
+impl Default for NanBox {
+    fn default() -> Self {
+        NanBox::NIL
+    }
+}
+
+impl Clone for NanBox {
+    fn clone(&self) -> Self {
+        if self.is_string() {
+            // SAFETY: the pointer came from `Rc::into_raw` and `self` still
+            // holds a strong reference to it.
+            unsafe { Rc::increment_strong_count(self.string_ptr()) };
+        }
+        NanBox::from_bits(self.bits)
+    }
+}
+
+impl Drop for NanBox {
+    fn drop(&mut self) {
+        if self.is_string() {
+            // SAFETY: releases the strong reference this box owns.
+            unsafe { Rc::decrement_strong_count(self.string_ptr()) };
+        }
+    }
+}
+
+impl From<f64> for NanBox {
+    fn from(value: f64) -> Self {
+        let value = if value.is_nan() { f64::NAN } else { value };
+        NanBox::from_bits(value.to_bits())
+    }
+}
+
+impl From<bool> for NanBox {
+    fn from(value: bool) -> Self {
+        if value {
+            NanBox::TRUE
+        } else {
+            NanBox::FALSE
+        }
+    }
+}
+
+impl From<Value> for NanBox {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Nil => NanBox::NIL,
+            Value::Boolean(v) => NanBox::from(v),
+            Value::Number(v) => NanBox::from(v),
+            Value::String(v) => {
+                // `Rc<str>` is a fat pointer, so it is boxed once more to
+                // get an address that fits in the payload. Clones share
+                // that box, which is why the VM boxes a chunk's constants
+                // once per run rather than on every load.
+                let ptr = Rc::into_raw(Rc::new(v)) as u64;
+                // Checked even in release builds: a pointer with tag bits set
+                // would silently decode as a different value.
+                assert_eq!(
+                    0,
+                    ptr & STRING,
+                    "pointer does not fit in a NaN box"
+                );
+                NanBox::from_bits(ptr | STRING)
+            }
+        }
+    }
+}
+
+impl From<NanBox> for Value {
+    fn from(value: NanBox) -> Self {
+        if let Some(v) = value.as_number() {
+            Value::Number(v)
+        } else if let Some(v) = value.as_string() {
+            Value::String(v.clone())
+        } else if value.bits == NanBox::NIL.bits {
+            Value::Nil
+        } else {
+            Value::Boolean(value.bits == NanBox::TRUE.bits)
+        }
+    }
+}
+
+impl From<NanBox> for bool {
+    fn from(value: NanBox) -> Self {
+        value.bits != NanBox::NIL.bits && value.bits != NanBox::FALSE.bits
+    }
+}
+
+impl PartialEq for NanBox {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_number(), other.as_number()) {
+            (Some(a), Some(b)) => a == b,
+            _ => match (self.as_string(), other.as_string()) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.bits == other.bits,
+            },
+        }
+    }
+}
+
+impl fmt::Display for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(v) = self.as_number() {
+            write!(f, "{}", v)
+        } else if let Some(v) = self.as_string() {
+            write!(f, "{}", v)
+        } else if self.bits == NanBox::NIL.bits {
+            write!(f, "nil")
+        } else {
+            write!(f, "{}", self.bits == NanBox::TRUE.bits)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
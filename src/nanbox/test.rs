@@ -0,0 +1,76 @@
+use std::mem::size_of;
+use std::rc::Rc;
+
+use super::NanBox;
+use crate::Value;
+
+fn round_trip(value: Value) -> Value {
+    Value::from(NanBox::from(value))
+}
+
+#[test]
+fn size() {
+    assert_eq!(8, size_of::<NanBox>());
+}
+
+#[test]
+fn conversions() {
+    assert!(matches!(round_trip(Value::Nil), Value::Nil));
+    assert!(matches!(
+        round_trip(Value::Boolean(true)),
+        Value::Boolean(true)
+    ));
+    assert!(matches!(
+        round_trip(Value::Boolean(false)),
+        Value::Boolean(false)
+    ));
+    for v in [0.0, -0.0, 1.5, -1e300, f64::INFINITY, f64::NEG_INFINITY] {
+        match round_trip(Value::Number(v)) {
+            Value::Number(n) => assert_eq!(v.to_bits(), n.to_bits()),
+            _ => panic!("expected a number"),
+        }
+    }
+    assert!(matches!(round_trip(Value::Number(f64::NAN)),
+                     Value::Number(n) if n.is_nan()));
+    assert!(matches!(round_trip(Value::Number(-f64::NAN)),
+                     Value::Number(n) if n.is_nan()));
+    match round_trip(Value::String("abc".into())) {
+        Value::String(s) => assert_eq!("abc", &*s),
+        _ => panic!("expected a string"),
+    }
+}
+
+#[test]
+fn same_semantics_as_value() {
+    let values = [
+        Value::Nil,
+        Value::Boolean(true),
+        Value::Boolean(false),
+        Value::Number(0.0),
+        Value::Number(-0.0),
+        Value::Number(2.5),
+        Value::Number(f64::NAN),
+        Value::String("".into()),
+        Value::String("nil".into()),
+        Value::String("2.5".into()),
+    ];
+    for a in &values {
+        let boxed = NanBox::from(a.clone());
+        assert_eq!(a.to_string(), boxed.to_string());
+        assert_eq!(bool::from(a.clone()), bool::from(boxed.clone()));
+        for b in &values {
+            assert_eq!(a == b, boxed == NanBox::from(b.clone()), "{a} == {b}");
+        }
+    }
+}
+
+#[test]
+fn string_reference_counts() {
+    let s: Rc<str> = "shared".into();
+    let boxed = NanBox::from(Value::String(s.clone()));
+    let copy = boxed.clone();
+    assert_eq!(2, Rc::strong_count(&s));
+    drop(boxed);
+    assert!(matches!(Value::from(copy), Value::String(v) if v == s));
+    assert_eq!(1, Rc::strong_count(&s));
+}
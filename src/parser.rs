@@ -1,10 +1,13 @@
 use anyhow::Error;
 use num_enum::UnsafeFromPrimitive;
 
-use crate::code::{Chunk, Op};
-use crate::scanner::{Scanner, Token, TokenType};
+use crate::code::{Chunk, Location, Op};
+use crate::scanner::{ScanError, Scanner, Token, TokenType};
+#[cfg(feature = "bench_mode")]
+use crate::Result;
 use crate::{Value, Vm};
 
+#[allow(dead_code)]
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, UnsafeFromPrimitive,
 )]
@@ -42,6 +45,38 @@ impl Prec {
     }
 }
 
+/// An error found while compiling, displayed as `[line N] Error...`.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[error("[line {line}] Error{at}: {message}")]
+pub struct CompileError {
+    line: u32,
+    column: u32,
+    at: String,
+    message: String,
+    incomplete: bool,
+}
+
+impl CompileError {
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// The column of the token the error was found at, counting from 1.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Whether the error is only due to the source ending too early, as
+    /// with an unclosed parenthesis or string.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+}
+
 pub struct Parser {
     scanner: Scanner,
     code: Vec<Chunk>,
@@ -49,6 +84,8 @@ pub struct Parser {
     previous: Token,
     had_error: bool,
     panic_mode: bool,
+    errors: Vec<CompileError>,
+    quiet: bool,
 }
 
 impl Parser {
@@ -60,29 +97,50 @@ impl Parser {
             previous: Token::default(),
             had_error: false,
             panic_mode: false,
+            errors: Vec::new(),
+            quiet: false,
         }
     }
 
-    pub fn parse(&mut self, vm: &mut Vm) -> Option<Chunk> {
+    /// Stops errors from being printed to stderr as they are found. They
+    /// are still available from `errors`.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// The errors found by `parse`.
+    pub fn errors(&self) -> &[CompileError] {
+        &self.errors
+    }
+
+    /// Whether parsing failed only because the source ended too early,
+    /// so that more input could complete it.
+    pub fn is_incomplete(&self) -> bool {
+        self.errors.first().is_some_and(CompileError::is_incomplete)
+    }
+
+    pub fn parse(&mut self, _vm: &mut Vm) -> Option<Chunk> {
         self.code.push(Chunk::new());
 
         self.advance();
         self.expression();
         self.consume(TokenType::Eof, "expect end of expression");
 
-        self.emit_op(Op::Return);
+        self.emit_op(Op::Return, self.previous);
+        self.optimize();
 
         #[cfg(feature = "print_code")]
         if !self.had_error {
-            self.chunk().disassemble("<script>");
+            print!("{}", self.chunk().disassembly("<script>"));
         }
 
         let chunk = self.code.pop().unwrap();
         (!self.had_error).then_some(chunk)
     }
 
-    #[cfg(debug_assertions)]
-    pub fn show_tokens(&mut self) {
+    /// Prints each token on a line of its own. Returns false if the source
+    /// could not be scanned, after reporting the error.
+    pub fn show_tokens(&mut self) -> bool {
         let mut line: u32 = 0;
         loop {
             self.advance();
@@ -101,6 +159,7 @@ impl Parser {
                 break;
             }
         }
+        !self.had_error
     }
 
     #[cfg(feature = "bench_mode")]
@@ -125,6 +184,16 @@ impl Parser {
         Ok(())
     }
 
+    fn optimize(&mut self) {
+        if self.had_error {
+            return;
+        }
+        match self.chunk().fold_constants() {
+            Ok(chunk) => *self.chunk() = chunk.peephole(),
+            Err(e) => self.error(&e.to_string()),
+        }
+    }
+
     fn chunk(&mut self) -> &mut Chunk {
         &mut self.code[0]
     }
@@ -135,10 +204,6 @@ impl Parser {
             match self.scanner.scan_token() {
                 Ok(token) => {
                     self.current = token;
-                    let line = self.current.line();
-                    if line != self.previous.line() {
-                        self.chunk().new_line(line);
-                    }
                     break;
                 }
                 Err(e) => self.scan_error(e),
@@ -178,6 +243,7 @@ impl Parser {
                 | TokenType::Plus
                 | TokenType::Slash
                 | TokenType::Star
+                | TokenType::BangEqual
                 | TokenType::EqualEqual
                 | TokenType::Greater
                 | TokenType::GreaterEqual
@@ -194,7 +260,7 @@ impl Parser {
             .token_text(self.previous)
             .parse::<f64>()
             .unwrap();
-        self.emit_constant(Value::Number(value));
+        self.emit_constant(Value::Number(value), self.previous);
     }
 
     fn literal(&mut self) {
@@ -204,12 +270,13 @@ impl Parser {
             TokenType::False => Op::False,
             _ => unreachable!(),
         };
-        self.emit_op(op);
+        self.emit_op(op, self.previous);
     }
 
     fn string(&mut self) {
         let raw = self.scanner.token_text(self.previous);
-        self.emit_constant(Value::String(raw[1..raw.len() - 1].into()));
+        let value = Value::String(raw[1..raw.len() - 1].into());
+        self.emit_constant(value, self.previous);
     }
 
     fn expression(&mut self) {
@@ -222,63 +289,75 @@ impl Parser {
     }
 
     fn unary(&mut self) {
-        let operator_type = self.previous.ty();
+        let operator = self.previous;
 
         self.parse_precedence(Prec::Unary);
 
-        match operator_type {
-            TokenType::Minus => self.emit_op(Op::Negate),
-            TokenType::Bang => self.emit_op(Op::Not),
+        match operator.ty() {
+            TokenType::Minus => self.emit_op(Op::Negate, operator),
+            TokenType::Bang => self.emit_op(Op::Not, operator),
             _ => unreachable!(),
         }
     }
 
     fn binary(&mut self) {
-        let operator_type = self.previous.ty();
-        self.parse_precedence(Prec::for_op_type(operator_type).next());
-
-        match operator_type {
-            TokenType::Plus => self.emit_op(Op::Add),
-            TokenType::Minus => self.emit_op(Op::Subtract),
-            TokenType::Star => self.emit_op(Op::Multiply),
-            TokenType::Slash => self.emit_op(Op::Divide),
-            TokenType::EqualEqual => self.emit_op(Op::Equal),
-            TokenType::Less => self.emit_op(Op::Less),
-            TokenType::Greater => self.emit_op(Op::Greater),
+        let operator = self.previous;
+        self.parse_precedence(Prec::for_op_type(operator.ty()).next());
+
+        match operator.ty() {
+            TokenType::Plus => self.emit_op(Op::Add, operator),
+            TokenType::Minus => self.emit_op(Op::Subtract, operator),
+            TokenType::Star => self.emit_op(Op::Multiply, operator),
+            TokenType::Slash => self.emit_op(Op::Divide, operator),
+            TokenType::EqualEqual => self.emit_op(Op::Equal, operator),
+            TokenType::Less => self.emit_op(Op::Less, operator),
+            TokenType::Greater => self.emit_op(Op::Greater, operator),
             TokenType::BangEqual => {
-                self.emit_op(Op::Equal);
-                self.emit_op(Op::Not);
+                self.emit_op(Op::Equal, operator);
+                self.emit_op(Op::Not, operator);
             }
             TokenType::GreaterEqual => {
-                self.emit_op(Op::Less);
-                self.emit_op(Op::Not);
+                self.emit_op(Op::Less, operator);
+                self.emit_op(Op::Not, operator);
             }
             TokenType::LessEqual => {
-                self.emit_op(Op::Greater);
-                self.emit_op(Op::Not);
+                self.emit_op(Op::Greater, operator);
+                self.emit_op(Op::Not, operator);
             }
             _ => unreachable!(),
         }
     }
 
-    fn emit_op(&mut self, op: Op) {
-        self.chunk().write_op(op);
+    /// Emits `op` at the location of the token it was compiled from.
+    fn emit_op(&mut self, op: Op, token: Token) {
+        self.set_location(token);
+        self.chunk().write_fused(op);
     }
 
-    fn emit_constant(&mut self, value: Value) {
-        let chunk = self.chunk();
-        let arg = match chunk.add_constant(value) {
-            Ok(idx) => idx,
-            Err(e) => {
-                self.error(&e.to_string());
-                return;
-            }
-        };
-        chunk.write_op_arg(Op::Constant, arg);
+    fn emit_constant(&mut self, value: Value, token: Token) {
+        self.set_location(token);
+        if let Err(e) = self.chunk().write_value(value) {
+            self.error(&e.to_string());
+        }
+    }
+
+    fn set_location(&mut self, token: Token) {
+        let location = Location::new(token.line(), token.column());
+        self.chunk().set_location(location);
     }
 
     fn scan_error(&mut self, err: Error) {
-        self.report_error(self.previous.line(), format!(": {}", err));
+        let incomplete = err.downcast_ref::<ScanError>()
+            == Some(&ScanError::UnterminatedString);
+        let (line, column) = self.scanner.location();
+        let error = CompileError {
+            line,
+            column,
+            at: String::new(),
+            message: err.to_string(),
+            incomplete,
+        };
+        self.report_error(error);
     }
 
     fn error(&mut self, msg: &str) {
@@ -286,19 +365,32 @@ impl Parser {
     }
 
     fn error_at(&mut self, token: Token, msg: &str) {
-        let msg = match token.ty() {
-            TokenType::Eof => format!(" at end: {}", msg),
-            _ => format!(" at '{}': {}", self.scanner.token_text(token), msg),
+        let at = match token.ty() {
+            TokenType::Eof => " at end".to_string(),
+            _ => format!(" at '{}'", self.scanner.token_text(token)),
+        };
+        let error = CompileError {
+            line: token.line(),
+            column: token.column(),
+            at,
+            message: msg.to_string(),
+            incomplete: token.ty() == TokenType::Eof,
         };
-        self.report_error(token.line(), msg);
+        self.report_error(error);
     }
 
-    fn report_error(&mut self, line: u32, msg: String) {
+    fn report_error(&mut self, error: CompileError) {
         if self.panic_mode {
             return;
         }
         self.panic_mode = true;
         self.had_error = true;
-        eprintln!("[line {}] Error{}", line, msg);
+        if !self.quiet {
+            eprintln!("{}", error);
+        }
+        self.errors.push(error);
     }
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,40 @@
+use super::Parser;
+use crate::Vm;
+
+fn parse(source: &str) -> Parser {
+    let mut parser = Parser::new(source.to_string());
+    parser.set_quiet(true);
+    parser.parse(&mut Vm::init());
+    parser
+}
+
+#[test]
+fn errors() {
+    assert!(parse("1 + 2").errors().is_empty());
+
+    let parser = parse("1 +\n)");
+    let errors = parser.errors();
+    assert_eq!(1, errors.len());
+    assert_eq!(2, errors[0].line());
+    assert_eq!(1, errors[0].column());
+    assert_eq!("expect expression", errors[0].message());
+    assert_eq!(
+        "[line 2] Error at ')': expect expression",
+        errors[0].to_string()
+    );
+
+    assert_eq!(
+        "[line 1] Error: unexpected character '@'",
+        parse("1 + @").errors()[0].to_string()
+    );
+}
+
+#[test]
+fn incomplete_input() {
+    for source in ["(1 + 2", "1 +", "-", "\"open", "((1)", ""] {
+        assert!(parse(source).is_incomplete(), "{:?}", source);
+    }
+    for source in ["1 + 2", "1)", "1 2", "1 + @", "(1 + 2))"] {
+        assert!(!parse(source).is_incomplete(), "{:?}", source);
+    }
+}
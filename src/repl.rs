@@ -0,0 +1,134 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use rlox::{Parser, Result, Vm};
+
+use editor::{Editor, Line};
+
+mod editor;
+
+/// Reads expressions and runs them until the end of input, echoing each
+/// value. Input that ends in the middle of an expression, like an
+/// unclosed parenthesis or string, is continued on the next line; an
+/// empty line ends it anyway. Errors are reported and the session goes
+/// on with the same VM.
+pub fn run(vm: &mut Vm) -> Result<()> {
+    let mut editor = Editor::new(history_path());
+    let mut line_no = 1;
+    let mut source = String::new();
+    loop {
+        let prompt = match source.is_empty() {
+            true => format!("{:4}> ", line_no),
+            false => format!("{:4}| ", line_no),
+        };
+        let line = match editor.read_line(&prompt)? {
+            Line::Text(line) => line,
+            Line::Cancelled => {
+                source.clear();
+                continue;
+            }
+            Line::Eof => break,
+        };
+        line_no += 1;
+        let blank = line.trim().is_empty();
+        if source.is_empty() && blank {
+            continue;
+        }
+        if source.is_empty() && line.starts_with(':') {
+            if let Err(e) = command(vm, &line) {
+                eprintln!("{}", e);
+            }
+            continue;
+        }
+        if !source.is_empty() {
+            source.push('\n');
+        }
+        source.push_str(&line);
+
+        let mut parser = Parser::new(source.clone());
+        parser.set_quiet(true);
+        match parser.parse(vm) {
+            Some(chunk) => {
+                source.clear();
+                // The value is echoed by OP_RETURN, so only errors need
+                // reporting here.
+                if let Err(e) = vm.interpret_chunk(chunk) {
+                    eprintln!("{}", e);
+                }
+            }
+            None if parser.is_incomplete() && !blank => {}
+            None => {
+                for error in parser.errors() {
+                    eprintln!("{}", error);
+                }
+                source.clear();
+            }
+        }
+    }
+    Ok(())
+}
+
+const COMMANDS: &[(&str, &str)] = &[
+    (
+        ":dis <expr>",
+        "show the bytecode compiled for an expression",
+    ),
+    (":tokens <src>", "show the tokens scanned from source"),
+    (":load <path>", "run a source or compiled file"),
+    (":globals", "list global variables"),
+    (":reset", "discard the suspended run and memory statistics"),
+    (
+        ":time <expr>",
+        "run an expression and show how long it took",
+    ),
+    (":help", "show this list"),
+];
+
+fn command(vm: &mut Vm, line: &str) -> Result<()> {
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+    match (name, arg) {
+        (":dis", expr) if !expr.is_empty() => {
+            let mut parser = Parser::new(expr.to_string());
+            if let Some(chunk) = parser.parse(vm) {
+                print!("{}", chunk.disassembly(expr));
+            }
+        }
+        (":tokens", src) if !src.is_empty() => {
+            Parser::new(src.to_string()).show_tokens();
+        }
+        (":load", path) if !path.is_empty() => crate::run_file(vm, path)?,
+        (":globals", "") => {
+            // Expressions cannot define variables yet, so there are none.
+            println!("no globals defined");
+        }
+        (":reset", "") => vm.reset(),
+        (":time", expr) if !expr.is_empty() => {
+            let start = Instant::now();
+            vm.interpret(expr.to_string())?;
+            eprintln!("{:?}", start.elapsed());
+        }
+        (":help", "") => {
+            for (usage, help) in COMMANDS {
+                println!("{:16} {}", usage, help);
+            }
+        }
+        _ => {
+            match COMMANDS
+                .iter()
+                .find(|(usage, _)| usage.split(' ').next() == Some(name))
+            {
+                Some((usage, _)) => eprintln!("Usage: {}", usage),
+                None => eprintln!("Unknown command {}; try :help", name),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rlox_history"))
+}
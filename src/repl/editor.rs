@@ -0,0 +1,369 @@
+//! A small line editor for the REPL, with cursor movement and history.
+//!
+//! While a line is read the terminal is switched out of canonical mode
+//! with `stty`, so no terminal library is needed. When stdin is not a
+//! terminal, or `stty` is not available, lines are read as they are.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use rlox::highlight_ansi;
+
+/// The most lines kept in the history file.
+const HISTORY_LEN: usize = 1000;
+
+pub enum Line {
+    Text(String),
+    /// The line was abandoned with Ctrl-C.
+    Cancelled,
+    Eof,
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl Editor {
+    /// Creates an editor whose history is loaded from and saved to
+    /// `history_path`.
+    pub fn new(history_path: Option<PathBuf>) -> Self {
+        let mut history: Vec<String> = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(String::from).collect())
+            .unwrap_or_default();
+        history.drain(..history.len().saturating_sub(HISTORY_LEN));
+        Editor {
+            history,
+            history_path,
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Line> {
+        print!("{}", prompt);
+        stdout().flush()?;
+        let raw = match stdin().is_terminal() {
+            true => RawMode::enable(),
+            false => None,
+        };
+        let line = match raw {
+            Some(raw) => {
+                let line = self.edit(prompt);
+                drop(raw);
+                line?
+            }
+            None => read_plain()?,
+        };
+        if let Line::Text(text) = &line {
+            self.add_history(text);
+        }
+        Ok(line)
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Line> {
+        let mut input = stdin().lock().bytes();
+        let mut buffer = LineBuffer::default();
+        // The history entry being shown, where `history.len()` is the
+        // new line, which is kept in `draft` while browsing.
+        let mut index = self.history.len();
+        let mut draft = String::new();
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None if buffer.chars.is_empty() => return Ok(Line::Eof),
+                None => Key::Enter,
+            };
+            match key {
+                Key::Enter => {
+                    println!();
+                    return Ok(Line::Text(buffer.text()));
+                }
+                Key::Interrupt => {
+                    println!("^C");
+                    return Ok(Line::Cancelled);
+                }
+                Key::Eof if buffer.chars.is_empty() => {
+                    println!();
+                    return Ok(Line::Eof);
+                }
+                Key::Eof => buffer.apply(Key::Delete),
+                Key::Up if index > 0 => {
+                    if index == self.history.len() {
+                        draft = buffer.text();
+                    }
+                    index -= 1;
+                    buffer.set(&self.history[index]);
+                }
+                Key::Down if index < self.history.len() => {
+                    index += 1;
+                    match self.history.get(index) {
+                        Some(text) => buffer.set(text),
+                        None => buffer.set(&draft),
+                    }
+                }
+                key => buffer.apply(key),
+            }
+            refresh(prompt, &buffer)?;
+        }
+    }
+
+    /// Adds `text` to the history and saves it. If the history cannot be
+    /// saved, that is reported once and the rest of the session is only
+    /// kept in memory.
+    fn add_history(&mut self, text: &str) {
+        if text.trim().is_empty()
+            || self.history.last().is_some_and(|last| last == text)
+        {
+            return;
+        }
+        self.history.push(text.to_string());
+        let full = self.history.len() > HISTORY_LEN;
+        if full {
+            self.history.remove(0);
+        }
+        if let Some(path) = &self.history_path {
+            if let Err(e) = save_history(path, &self.history, full) {
+                eprintln!("rlox: cannot save history: {}", e);
+                self.history_path = None;
+            }
+        }
+    }
+}
+
+/// Appends the last entry of `history` to the file at `path`, or with
+/// `rewrite` replaces the file with all of it, so the file stays at most
+/// `HISTORY_LEN` lines long.
+fn save_history(
+    path: &Path,
+    history: &[String],
+    rewrite: bool,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(!rewrite)
+        .truncate(rewrite)
+        .open(path)?;
+    let entries = match rewrite {
+        true => history,
+        false => &history[history.len() - 1..],
+    };
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(entry);
+        text.push('\n');
+    }
+    file.write_all(text.as_bytes())
+}
+
+fn read_plain() -> io::Result<Line> {
+    let mut text = String::new();
+    if stdin().lock().read_line(&mut text)? == 0 {
+        return Ok(Line::Eof);
+    }
+    let len = text.trim_end_matches(['\n', '\r']).len();
+    text.truncate(len);
+    Ok(Line::Text(text))
+}
+
+/// Redraws the line, highlighted, and puts the cursor back where it
+/// belongs.
+fn refresh(prompt: &str, buffer: &LineBuffer) -> io::Result<()> {
+    let column = prompt.chars().count() + buffer.cursor;
+    let text = highlight_ansi(&buffer.text());
+    print!("\r{}{}\x1b[K\r", prompt, text);
+    if column > 0 {
+        print!("\x1b[{}C", column);
+    }
+    stdout().flush()
+}
+
+/// Keeps the terminal out of canonical mode until it is dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&[
+            "-icanon", "-echo", "-isig", "-ixon", "min", "1", "time", "0",
+        ])?;
+        Some(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToEnd,
+    KillToStart,
+    Interrupt,
+    Eof,
+    Other,
+}
+
+/// Reads one key press, decoding escape sequences and UTF-8. Returns
+/// `None` at the end of the input.
+fn read_key<I>(input: &mut I) -> io::Result<Option<Key>>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let byte = match input.next() {
+        Some(byte) => byte?,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillToEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillToStart,
+        0x1b => read_escape(input)?,
+        0x00..=0x1f => Key::Other,
+        0x20..=0x7f => Key::Char(byte as char),
+        _ => {
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => return Ok(Some(Key::Other)),
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match input.next() {
+                    Some(byte) => bytes.push(byte?),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes) {
+                Ok(text) => Key::Char(text.chars().next().unwrap()),
+                Err(_) => Key::Other,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// Decodes the rest of an escape sequence, like `[A` for the up arrow.
+fn read_escape<I>(input: &mut I) -> io::Result<Key>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let mut next = || input.next().transpose();
+    if !matches!(next()?, Some(b'[' | b'O')) {
+        return Ok(Key::Other);
+    }
+    let key = match next()? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') => {
+            let mut code = vec![digit];
+            loop {
+                match next()? {
+                    Some(b'~') | None => break,
+                    Some(byte) => code.push(byte),
+                }
+            }
+            match code.as_slice() {
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                b"3" => Key::Delete,
+                _ => Key::Other,
+            }
+        }
+        _ => Key::Other,
+    };
+    Ok(key)
+}
+
+/// The text being edited and the cursor position within it.
+#[derive(Default)]
+struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn apply(&mut self, key: Key) {
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::KillToEnd => self.chars.truncate(self.cursor),
+            Key::KillToStart => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
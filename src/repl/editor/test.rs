@@ -0,0 +1,77 @@
+use std::{env, fs, io, process};
+
+use super::{read_key, Editor, Key, LineBuffer, HISTORY_LEN};
+
+fn keys(input: &[u8]) -> Vec<Key> {
+    let mut bytes = input.iter().map(|&b| Ok::<u8, io::Error>(b));
+    let mut keys = Vec::new();
+    while let Some(key) = read_key(&mut bytes).unwrap() {
+        keys.push(key);
+    }
+    keys
+}
+
+#[test]
+fn decode_keys() {
+    assert_eq!(
+        vec![
+            Key::Char('a'),
+            Key::Char('é'),
+            Key::Up,
+            Key::Left,
+            Key::Home,
+            Key::Delete,
+            Key::End,
+            Key::Backspace,
+            Key::Interrupt,
+            Key::Enter,
+        ],
+        keys("aé\x1b[A\x1b[D\x1bOH\x1b[3~\x1b[4~\x7f\x03\r".as_bytes())
+    );
+}
+
+#[test]
+fn edit_line() {
+    let mut buffer = LineBuffer::default();
+    for key in keys(b"1+3\x1b[D\x7f2 \x1b[C\x01-(\x05)") {
+        buffer.apply(key);
+    }
+    assert_eq!("-(12 3)", buffer.text());
+    assert_eq!(7, buffer.cursor);
+
+    for key in keys(b"\x1b[D\x1b[D\x0b") {
+        buffer.apply(key);
+    }
+    assert_eq!("-(12 ", buffer.text());
+    for key in keys(b"\x1b[D\x15") {
+        buffer.apply(key);
+    }
+    assert_eq!(" ", buffer.text());
+    assert_eq!(0, buffer.cursor);
+}
+
+#[test]
+fn history_file() {
+    let path = env::temp_dir().join(format!("rlox-history-{}", process::id()));
+    let _ = fs::remove_file(&path);
+    let mut editor = Editor::new(Some(path.clone()));
+    for i in 0..HISTORY_LEN + 5 {
+        editor.add_history(&i.to_string());
+        editor.add_history(&i.to_string());
+    }
+    editor.add_history(" ");
+
+    let text = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(HISTORY_LEN, lines.len());
+    assert_eq!(["5", "6"], lines[..2]);
+    assert_eq!(Some(&"1004"), lines.last());
+    assert_eq!(lines, Editor::new(Some(path.clone())).history);
+    fs::remove_file(&path).unwrap();
+
+    // A history that cannot be saved is still kept for the session.
+    let mut editor = Editor::new(Some(env::temp_dir()));
+    editor.add_history("1 + 2");
+    assert_eq!(None, editor.history_path);
+    assert_eq!(vec!["1 + 2"], editor.history);
+}
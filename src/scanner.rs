@@ -43,13 +43,24 @@ pub enum TokenType {
     True,
     Var,
     While,
+    /// A `//` comment, only produced once `Scanner::set_keep_comments`
+    /// is on.
+    Comment,
     #[default]
     Eof,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum ScanError {
+    #[error("unexpected character '{0}'")]
+    UnexpectedCharacter(char),
+    #[error("unterminated string")]
+    UnterminatedString,
+}
+
 impl fmt::Display for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", format!("{self:?}").to_ascii_uppercase())
+        f.pad(&format!("{self:?}").to_ascii_uppercase())
     }
 }
 
@@ -59,6 +70,7 @@ pub struct Token {
     start: usize,
     end: usize,
     line: u32,
+    column: u32,
 }
 
 impl Token {
@@ -77,10 +89,17 @@ impl Token {
         self.end
     }
 
+    /// The line the token starts on.
     #[inline]
     pub fn line(&self) -> u32 {
         self.line
     }
+
+    /// The byte column the token starts at, counting from 1.
+    #[inline]
+    pub fn column(&self) -> u32 {
+        self.column
+    }
 }
 
 impl Default for Token {
@@ -90,6 +109,7 @@ impl Default for Token {
             start: 0,
             end: 0,
             line: 1,
+            column: 1,
         }
     }
 }
@@ -97,6 +117,7 @@ impl Default for Token {
 struct Source {
     text: Vec<u8>,
     current: usize,
+    line_start: usize,
 }
 
 impl Source {
@@ -104,14 +125,19 @@ impl Source {
         Source {
             text: text.into_bytes(),
             current: 0,
+            line_start: 0,
         }
     }
 
     fn next(&mut self) -> Option<u8> {
-        self.peek().map(|c| {
-            self.current += 1;
-            c
-        })
+        self.peek().inspect(|&c| self.bump(c))
+    }
+
+    fn bump(&mut self, c: u8) {
+        self.current += 1;
+        if c == b'\n' {
+            self.line_start = self.current;
+        }
     }
 
     fn peek(&self) -> Option<u8> {
@@ -131,9 +157,9 @@ impl Source {
     where
         P: FnMut(u8) -> bool,
     {
-        self.peek().map_or(false, |c| {
+        self.peek().is_some_and(|c| {
             predicate(c) && {
-                self.current += 1;
+                self.bump(c);
                 true
             }
         })
@@ -151,6 +177,11 @@ pub struct Scanner {
     source: Source,
     start: usize,
     line: u32,
+    /// Where the current token starts, which for a multi-line string is
+    /// before `line`.
+    start_line: u32,
+    column: u32,
+    keep_comments: bool,
 }
 
 impl Scanner {
@@ -159,25 +190,36 @@ impl Scanner {
             source: Source::new(text),
             start: 0,
             line: 1,
+            start_line: 1,
+            column: 1,
+            keep_comments: false,
         }
     }
 
+    /// Returns comments as `Comment` tokens instead of skipping them, for
+    /// tools that reproduce the source.
+    pub fn set_keep_comments(&mut self, keep: bool) {
+        self.keep_comments = keep;
+    }
+
     pub fn token_text(&self, token: Token) -> &str {
         unsafe {
             str::from_utf8_unchecked(&self.source.text[token.start..token.end])
         }
     }
 
-    pub fn line(&self) -> u32 {
-        self.line
+    /// The line and column the last token, or the text that failed to
+    /// scan, starts at.
+    pub fn location(&self) -> (u32, u32) {
+        (self.start_line, self.column)
     }
 
     fn is_digit(c: u8) -> bool {
-        (b'0'..=b'9').contains(&c)
+        c.is_ascii_digit()
     }
 
     fn is_alpha(c: u8) -> bool {
-        (b'a'..=b'z').contains(&c) || (b'A'..=b'Z').contains(&c) || c == b'_'
+        c.is_ascii_alphabetic() || c == b'_'
     }
 
     fn is_ident(c: u8) -> bool {
@@ -204,7 +246,14 @@ impl Scanner {
             b'.' => self.make_token(TokenType::Dot),
             b'-' => self.make_token(TokenType::Minus),
             b'+' => self.make_token(TokenType::Plus),
-            b'/' => self.make_token(TokenType::Slash),
+            b'/' => {
+                if self.matches(b'/') {
+                    self.source.skip_while(|c| c != b'\n');
+                    self.make_token(TokenType::Comment)
+                } else {
+                    self.make_token(TokenType::Slash)
+                }
+            }
             b'*' => self.make_token(TokenType::Star),
             b'!' => {
                 if self.matches(b'=') {
@@ -235,7 +284,7 @@ impl Scanner {
                 }
             }
             b'"' => self.string()?,
-            _ => bail!("unexpected character '{}'", c as char),
+            _ => bail!(ScanError::UnexpectedCharacter(self.unexpected(c))),
         };
         Ok(token)
     }
@@ -250,17 +299,33 @@ impl Scanner {
                     }
             });
 
-            if self.source.peek() == Some(b'/')
+            if !self.keep_comments
+                && self.source.peek() == Some(b'/')
                 && self.source.peek_peek() == Some(b'/')
             {
                 self.source.skip_while(|c| c != b'\n');
-                self.source.next();
                 continue;
             }
             break;
         }
 
         self.start = self.source.current;
+        self.start_line = self.line;
+        self.column = (self.start - self.source.line_start) as u32 + 1;
+    }
+
+    /// Skips the rest of a character that cannot start a token, which
+    /// may take several bytes, and returns it.
+    fn unexpected(&mut self, c: u8) -> char {
+        if c.is_ascii() {
+            return c as char;
+        }
+        self.source.skip_while(|c| c & 0xc0 == 0x80);
+        let bytes = &self.source.text[self.start..self.source.current];
+        str::from_utf8(bytes)
+            .ok()
+            .and_then(|text| text.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
     }
 
     fn make_token(&mut self, ty: TokenType) -> Token {
@@ -268,7 +333,8 @@ impl Scanner {
             ty,
             start: self.start,
             end: self.source.current,
-            line: self.line,
+            line: self.start_line,
+            column: self.column,
         }
     }
 
@@ -284,7 +350,7 @@ impl Scanner {
             } || c != b'"'
         });
         if self.source.peek().is_none() {
-            bail!("unterminated string");
+            bail!(ScanError::UnterminatedString);
         }
         self.source.next();
         Ok(self.make_token(TokenType::String))
@@ -293,7 +359,7 @@ impl Scanner {
     fn number(&mut self) -> Token {
         self.source.skip_while(Scanner::is_digit);
         if self.source.peek() == Some(b'.')
-            && self.source.peek_peek().map_or(false, Scanner::is_digit)
+            && self.source.peek_peek().is_some_and(Scanner::is_digit)
         {
             self.source.next();
             self.source.skip_while(Scanner::is_digit);
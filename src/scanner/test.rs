@@ -119,6 +119,7 @@ fn strings() -> Result<()> {
     Ok(())
 }
 
+#[test]
 fn whitespace() -> Result<()> {
     let source = r#"
     space    tabs				newlines
@@ -139,6 +140,56 @@ fn whitespace() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn locations() -> Result<()> {
+    let source = "a  bc\n  // comment\n\t\"x\ny\" d";
+    let mut scanner = Scanner::new(source.into());
+
+    let mut locations = Vec::new();
+    loop {
+        let token = scanner.scan_token()?;
+        locations.push((token.line(), token.column()));
+        if token.ty() == TokenType::Eof {
+            break;
+        }
+    }
+    assert_eq!(vec![(1, 1), (1, 4), (3, 2), (4, 4), (4, 5)], locations);
+
+    Ok(())
+}
+
+#[test]
+fn unexpected_characters() -> Result<()> {
+    let mut scanner = Scanner::new("1 é @".into());
+
+    assert_eq!((TokenType::Number, "1"), tok(&mut scanner)?);
+    let err = scanner.scan_token().err().unwrap();
+    assert_eq!("unexpected character 'é'", err.to_string());
+    assert_eq!((1, 3), scanner.location());
+    let err = scanner.scan_token().err().unwrap();
+    assert_eq!("unexpected character '@'", err.to_string());
+    assert_eq!((1, 6), scanner.location());
+    assert_eq!(TokenType::Eof, tok(&mut scanner)?.0);
+
+    Ok(())
+}
+
+#[test]
+fn comments() -> Result<()> {
+    let source = "1 // one\n/ 2 //\n";
+    let mut scanner = Scanner::new(source.into());
+    scanner.set_keep_comments(true);
+
+    assert_eq!((TokenType::Number, "1"), tok(&mut scanner)?);
+    assert_eq!((TokenType::Comment, "// one"), tok(&mut scanner)?);
+    assert_eq!((TokenType::Slash, "/"), tok(&mut scanner)?);
+    assert_eq!((TokenType::Number, "2"), tok(&mut scanner)?);
+    assert_eq!((TokenType::Comment, "//"), tok(&mut scanner)?);
+    assert_eq!(TokenType::Eof, tok(&mut scanner)?.0);
+
+    Ok(())
+}
+
 fn tok(scanner: &mut Scanner) -> Result<(TokenType, &str)> {
     let token = scanner.scan_token()?;
     Ok((token.ty(), scanner.token_text(token)))
@@ -1,164 +1,602 @@
-use crate::code::{Chunk, Op};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::code::{Bytecode, Chunk, Op};
 use crate::parser::Parser;
 use crate::Value;
 
 type Result<T> = std::result::Result<T, RuntimeError>;
 
+/// How values are stored on the stack while a chunk runs.
+#[cfg(not(feature = "nan_boxing"))]
+type Slot = Value;
+#[cfg(feature = "nan_boxing")]
+type Slot = crate::nanbox::NanBox;
+
 pub struct Vm {
-    stack: Vec<Value>,
+    stack: Vec<Slot>,
+    stack_limit: usize,
+    ip: usize,
+    fuel: Option<u64>,
+    suspended: Option<Loaded>,
+    interrupt: Arc<AtomicBool>,
+    memory_limit: Option<usize>,
+    bytes_allocated: usize,
+    peak_allocated: usize,
+    trace: bool,
+    args: Vec<String>,
+    output: Box<dyn Write>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MemoryStats {
+    /// Bytes allocated so far by the run in progress or suspended,
+    /// including its chunk. Strings are not counted as freed when they
+    /// are dropped, so this only grows during a run, and it is 0 once the
+    /// run has finished.
+    pub current: usize,
+    /// The most bytes a single run of this VM has allocated, since it was
+    /// created or last reset.
+    pub peak: usize,
+    pub limit: Option<usize>,
+}
+
+/// Stops a running `Vm` from another thread.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes the current or suspended run fail with an error of kind
+    /// `ErrorKind::Interrupted`. Has no effect on a later `interpret`.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
 }
 
 impl Vm {
-    const MAX_STACK: usize = 1024;
+    const DEFAULT_STACK_LIMIT: usize = 1024;
 
     pub fn init() -> Self {
-        Vm { stack: Vec::new() }
+        Vm::with_stack_capacity(0)
+    }
+
+    /// Creates a VM whose value stack starts with room for `capacity`
+    /// values. The stack still grows as needed, up to the stack limit.
+    pub fn with_stack_capacity(capacity: usize) -> Self {
+        Vm {
+            stack: Vec::with_capacity(capacity),
+            stack_limit: Vm::DEFAULT_STACK_LIMIT,
+            ip: 0,
+            fuel: None,
+            suspended: None,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            memory_limit: None,
+            bytes_allocated: 0,
+            peak_allocated: 0,
+            trace: false,
+            args: Vec::new(),
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Writes the stack and each instruction as it runs to the output set
+    /// with `set_output`, which is stdout by default.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Sends the values scripts print, and the trace, to `output` instead
+    /// of stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Sets the arguments the host passes to the script, such as the
+    /// command-line arguments after its input. Scripts cannot read them
+    /// until the language has globals; until then they are for the host.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
     }
 
-    fn error(msg: &str) -> Result<()> {
+    /// Limits the total bytes a run may allocate, counting its chunk's code
+    /// and constants as well as every string built at runtime, even ones
+    /// already dropped. Exceeding the limit fails the run with an error of
+    /// kind `ErrorKind::OutOfMemory`.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            current: self.bytes_allocated,
+            peak: self.peak_allocated,
+            limit: self.memory_limit,
+        }
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Result<()> {
+        let total = self.bytes_allocated.saturating_add(bytes);
+        if self.memory_limit.is_some_and(|limit| total > limit) {
+            return Err(RuntimeError::out_of_memory());
+        }
+        self.bytes_allocated = total;
+        self.peak_allocated = self.peak_allocated.max(total);
+        Ok(())
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupt.clone())
+    }
+
+    /// Sets the maximum number of values on the stack. A chunk that could
+    /// need more fails with a stack overflow error before it starts.
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    pub fn stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+
+    /// Limits how many instructions later runs may execute; `None` removes
+    /// the limit. A run that exhausts its fuel stops with an error of kind
+    /// `ErrorKind::FuelExhausted` and can be continued with `resume`.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Adds `fuel` to the remaining budget and continues the run that
+    /// exhausted it.
+    pub fn resume(&mut self, fuel: u64) -> Result<()> {
+        let loaded = match self.suspended.take() {
+            Some(loaded) => loaded,
+            None => return Vm::error("no suspended script to resume"),
+        };
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+        self.execute(loaded)
+    }
+
+    fn error<T>(msg: &str) -> Result<T> {
         Err(RuntimeError::new(msg.to_string()))
     }
 
     pub fn interpret(&mut self, source: String) -> Result<()> {
         let mut parser = Parser::new(source);
         match parser.parse(self) {
-            Some(chunk) => self.run(&chunk),
+            Some(chunk) => self.interpret_chunk(chunk),
             None => Ok(()),
         }
     }
 
-    pub(crate) fn run(&mut self, chunk: &Chunk) -> Result<()> {
-        let mut ip = chunk.instructions();
-        while let Some(inst) = ip.next() {
-            #[cfg(feature = "trace_execution")]
-            {
-                self.trace_stack();
-                chunk.disassemble_instruction(inst, ip.offset - inst.len());
+    /// Runs an already compiled chunk, such as one loaded with
+    /// `Chunk::read_from`.
+    pub fn interpret_chunk(&mut self, chunk: Chunk) -> Result<()> {
+        self.start(&chunk)?;
+        self.allocate(chunk.heap_size())?;
+        self.execute(Loaded::new(chunk))
+    }
+
+    /// Discards the suspended run, if any, and what it allocated, along
+    /// with the peak memory statistic. Limits, fuel and other settings are
+    /// kept.
+    pub fn reset(&mut self) {
+        self.discard_run();
+        self.peak_allocated = 0;
+    }
+
+    fn discard_run(&mut self) {
+        self.suspended = None;
+        self.stack.clear();
+        self.ip = 0;
+        self.bytes_allocated = 0;
+        self.interrupt.store(false, Ordering::Relaxed);
+    }
+
+    /// Discards the previous run and makes room for `chunk`'s stack.
+    fn start(&mut self, chunk: &Chunk) -> Result<()> {
+        self.discard_run();
+        // Checked before anything is allocated, so a chunk that cannot
+        // run leaves nothing behind.
+        if chunk.max_stack() > self.stack_limit {
+            let error = RuntimeError::new("stack overflow".to_string());
+            return Err(error.with_line(chunk.get_line(0)));
+        }
+        self.stack.reserve(chunk.max_stack());
+        Ok(())
+    }
+
+    fn execute(&mut self, loaded: Loaded) -> Result<()> {
+        let result = self.run(&loaded.chunk, loaded.constants());
+        match &result {
+            Err(e) if e.kind() == ErrorKind::FuelExhausted => {
+                self.suspended = Some(loaded);
+            }
+            _ => {
+                // Nothing outlives a finished run, so everything it
+                // allocated is freed along with the chunk.
+                self.ip = 0;
+                self.bytes_allocated = 0;
+            }
+        }
+        result
+    }
+
+    fn run(&mut self, chunk: &Chunk, constants: &[Slot]) -> Result<()> {
+        let code = chunk.code();
+        let start = code.as_ptr();
+        let end = code.as_ptr_range().end;
+        assert!(self.ip <= code.len());
+        // SAFETY: `self.ip` is 0 or the offset of an instruction in this
+        // chunk that a suspended run stopped at.
+        let mut ip = unsafe { start.add(self.ip) };
+        while ip < end {
+            // SAFETY: `ip < end` is checked above.
+            let offset = unsafe { ip.offset_from(start) } as usize;
+            let [mut opcode, arg] = unsafe { *ip }.to_be_bytes();
+            let mut operand = arg as u32;
+            ip = unsafe { ip.add(1) };
+            if opcode == Op::Extend as u8 {
+                (opcode, operand, ip) = Vm::decode_extended(operand, ip, end);
             }
+            // SAFETY: chunks are only made by the compiler, and by the
+            // assembler and the file loader, which verify them, so every
+            // opcode is a valid `Op`.
+            let op = unsafe { Op::from_unchecked(opcode) };
 
-            let result = match inst.opcode() {
-                Op::Nil => self.push(Value::Nil),
-                Op::True => self.push(Value::TRUE),
-                Op::False => self.push(Value::FALSE),
-                Op::Return => {
-                    println!("{}", self.pop());
-                    break;
-                }
-                Op::Not => {
-                    let arg = bool::from(self.pop());
-                    self.push(Value::Boolean(!arg))
-                }
-                Op::Negate => {
-                    let arg = self.pop();
-                    match arg {
-                        Value::Number(v) => self.push(Value::Number(-v)),
-                        _ => Vm::error("operand must be a number"),
-                    }
-                }
-                Op::Equal => {
-                    let a = self.pop();
-                    let b = self.pop();
-                    self.push(Value::Boolean(a == b))
-                }
-                Op::Greater => {
-                    let (a, b) = self.arithmetic_args()?;
-                    self.push(Value::Boolean(a > b))
-                }
-                Op::Less => {
-                    let (a, b) = self.arithmetic_args()?;
-                    self.push(Value::Boolean(a < b))
-                }
-                Op::Add => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    match (a, b) {
-                        (Value::Number(a), Value::Number(b)) => {
-                            self.push(Value::Number(a + b))
-                        }
-                        (Value::String(a), Value::String(b)) => {
-                            self.push(Value::String([a, b].concat().into()))
-                        }
-                        _ => Err(RuntimeError::new(
-                            "operands must be numbers".to_string(),
-                        )),
-                    }
-                }
-                Op::Subtract => {
-                    let (a, b) = self.arithmetic_args()?;
-                    self.push(Value::Number(a - b))
-                }
-                Op::Multiply => {
-                    let (a, b) = self.arithmetic_args()?;
-                    self.push(Value::Number(a * b))
-                }
-                Op::Divide => {
-                    let (a, b) = self.arithmetic_args()?;
-                    self.push(Value::Number(a / b))
+            // Without jumps or calls there is no backward edge to poll
+            // at, so check before every instruction.
+            if self.interrupt.load(Ordering::Relaxed) {
+                self.interrupt.store(false, Ordering::Relaxed);
+                self.stack.clear();
+                let line = chunk.get_line(offset);
+                return Err(RuntimeError::interrupted().with_line(line));
+            }
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    self.ip = offset;
+                    let line = chunk.get_line(offset);
+                    return Err(RuntimeError::fuel_exhausted().with_line(line));
                 }
-                Op::Constant => {
-                    let constant = chunk.get_constant(inst.operand());
-                    self.push(constant)
+                *fuel -= 1;
+            }
+
+            if self.trace {
+                self.trace_instruction(chunk, offset)
+                    .map_err(Vm::output_error)?;
+            }
+
+            match self.step(constants, op, operand) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    let line = chunk.get_line(offset);
+                    self.stack.clear();
+                    return Err(e.with_line(line));
                 }
-                _ => Vm::error("unknown opcode"),
-            };
-            result.map_err(|e| {
-                let offset = ip.offset - inst.len();
-                let line = chunk.get_line(offset);
-                self.stack.clear();
-                e.with_line(line)
-            })?;
+            }
         }
 
         Ok(())
     }
 
-    fn push(&mut self, val: Value) -> Result<()> {
-        if self.stack.len() < Vm::MAX_STACK {
-            self.stack.push(val);
-            Ok(())
-        } else {
-            Vm::error("stack overflow")
+    /// Finishes decoding an instruction with `OP_EXTEND` prefixes, given
+    /// the first prefix's operand and a pointer just past it. Returns the
+    /// opcode, the full operand and a pointer past the instruction.
+    #[cold]
+    #[inline(never)]
+    fn decode_extended(
+        mut operand: u32,
+        mut ip: *const Bytecode,
+        end: *const Bytecode,
+    ) -> (u8, u32, *const Bytecode) {
+        loop {
+            assert!(ip < end, "dangling OP_EXTEND");
+            // SAFETY: `ip < end` is checked above.
+            let [opcode, arg] = unsafe { *ip }.to_be_bytes();
+            ip = unsafe { ip.add(1) };
+            operand = operand << 8 | arg as u32;
+            if opcode != Op::Extend as u8 {
+                return (opcode, operand, ip);
+            }
+        }
+    }
+
+    /// Executes one instruction, returning false once the chunk has
+    /// returned.
+    fn step(
+        &mut self,
+        constants: &[Slot],
+        op: Op,
+        operand: u32,
+    ) -> Result<bool> {
+        match op {
+            Op::Nil => self.push(Slot::default()),
+            Op::True => self.push(Slot::from(true)),
+            Op::False => self.push(Slot::from(false)),
+            Op::Return => {
+                let value = self.pop();
+                writeln!(self.output, "{}", value).map_err(Vm::output_error)?;
+                return Ok(false);
+            }
+            Op::Not => {
+                let arg = bool::from(self.pop());
+                self.push(Slot::from(!arg))
+            }
+            Op::Negate => match self.pop().as_number() {
+                Some(v) => self.push(Slot::from(-v)),
+                None => return Vm::error("operand must be a number"),
+            },
+            Op::Equal => {
+                let a = self.pop();
+                let b = self.pop();
+                self.push(Slot::from(a == b))
+            }
+            Op::Greater => {
+                let (a, b) = self.arithmetic_args()?;
+                self.push(Slot::from(a > b))
+            }
+            Op::Less => {
+                let (a, b) = self.arithmetic_args()?;
+                self.push(Slot::from(a < b))
+            }
+            Op::NotEqual => {
+                let a = self.pop();
+                let b = self.pop();
+                self.push(Slot::from(a != b))
+            }
+            // These stand for `OP_LESS OP_NOT` and `OP_GREATER OP_NOT`, so
+            // comparisons with NaN must come out true.
+            Op::GreaterEqual => {
+                let (a, b) = self.arithmetic_args()?;
+                #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                let result = !(a < b);
+                self.push(Slot::from(result))
+            }
+            Op::LessEqual => {
+                let (a, b) = self.arithmetic_args()?;
+                #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                let result = !(a > b);
+                self.push(Slot::from(result))
+            }
+            Op::Add => {
+                let b = self.pop();
+                let a = self.pop();
+                self.add(a, b)?
+            }
+            Op::Subtract => {
+                let (a, b) = self.arithmetic_args()?;
+                self.push(Slot::from(a - b))
+            }
+            Op::Multiply => {
+                let (a, b) = self.arithmetic_args()?;
+                self.push(Slot::from(a * b))
+            }
+            Op::Divide => {
+                let (a, b) = self.arithmetic_args()?;
+                self.push(Slot::from(a / b))
+            }
+            Op::Constant0 => self.push(Slot::from(0.0)),
+            Op::Constant1 => self.push(Slot::from(1.0)),
+            Op::Constant => {
+                let constant = constants[operand as usize].clone();
+                self.push(constant)
+            }
+            Op::AddConstant => {
+                let b = constants[operand as usize].clone();
+                let a = self.pop();
+                self.add(a, b)?
+            }
+            _ => return Vm::error("unknown opcode"),
         }
+        Ok(true)
     }
 
-    fn pop(&mut self) -> Value {
+    /// Room for the chunk's values is reserved before it runs, so pushing
+    /// never needs to check the stack limit.
+    #[inline]
+    fn push(&mut self, val: Slot) {
+        debug_assert!(self.stack.len() < self.stack.capacity());
+        self.stack.push(val);
+    }
+
+    fn pop(&mut self) -> Slot {
         assert!(!self.stack.is_empty());
         self.stack.pop().unwrap()
     }
 
+    fn add(&mut self, a: Slot, b: Slot) -> Result<()> {
+        if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            self.push(Slot::from(a + b))
+        } else {
+            // Only a conversion when values are NaN-boxed.
+            #[allow(clippy::useless_conversion)]
+            match (Value::from(a), Value::from(b)) {
+                (Value::String(a), Value::String(b)) => {
+                    self.allocate(a.len() + b.len())?;
+                    let s = Value::String([a, b].concat().into());
+                    self.push(Slot::from(s))
+                }
+                _ => return Vm::error("operands must be numbers"),
+            }
+        }
+        Ok(())
+    }
+
     fn arithmetic_args(&mut self) -> Result<(f64, f64)> {
         let b = self.pop();
         let a = self.pop();
-        match (a, b) {
-            (Value::Number(a), Value::Number(b)) => Ok((a, b)),
+        match (a.as_number(), b.as_number()) {
+            (Some(a), Some(b)) => Ok((a, b)),
             _ => Err(RuntimeError::new("operands must be numbers".to_string())),
         }
     }
 
-    #[cfg(feature = "trace_execution")]
-    fn trace_stack(&self) {
-        print!("          ");
+    #[cold]
+    fn trace_instruction(
+        &mut self,
+        chunk: &Chunk,
+        offset: usize,
+    ) -> io::Result<()> {
+        write!(self.output, "          ")?;
         for elem in &self.stack {
-            print!("[ {} ]", elem);
+            write!(self.output, "[ {} ]", elem)?;
+        }
+        writeln!(self.output)?;
+        writeln!(self.output, "{}", chunk.disassemble_at(offset))
+    }
+
+    #[cold]
+    fn output_error(e: io::Error) -> RuntimeError {
+        RuntimeError::new(format!("cannot write output: {}", e))
+    }
+}
+
+#[cfg(feature = "bench_mode")]
+impl Vm {
+    /// Runs `chunk` `iterations` times, returning the time spent running.
+    /// Fuel would make the runs stop partway, so it must not be set.
+    pub fn bench(
+        &mut self,
+        chunk: &Chunk,
+        iterations: usize,
+    ) -> Result<std::time::Duration> {
+        if self.fuel.is_some() {
+            return Vm::error("cannot bench with a fuel limit");
+        }
+        self.start(chunk)?;
+        #[cfg(feature = "nan_boxing")]
+        let constants = &Loaded::box_constants(chunk);
+        #[cfg(not(feature = "nan_boxing"))]
+        let constants = chunk.constants();
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            self.ip = 0;
+            self.bytes_allocated = 0;
+            self.run(chunk, constants)?;
+        }
+        Ok(start.elapsed())
+    }
+}
+
+/// A chunk being run, with its constants in the stack's representation.
+/// They are converted once when the run starts, not again on `resume`.
+struct Loaded {
+    chunk: Chunk,
+    #[cfg(feature = "nan_boxing")]
+    constants: Vec<Slot>,
+}
+
+impl Loaded {
+    fn new(chunk: Chunk) -> Self {
+        Loaded {
+            #[cfg(feature = "nan_boxing")]
+            constants: Loaded::box_constants(&chunk),
+            chunk,
         }
-        println!();
+    }
+
+    fn constants(&self) -> &[Slot] {
+        #[cfg(not(feature = "nan_boxing"))]
+        return self.chunk.constants();
+        #[cfg(feature = "nan_boxing")]
+        return &self.constants;
+    }
+
+    #[cfg(feature = "nan_boxing")]
+    fn box_constants(chunk: &Chunk) -> Vec<Slot> {
+        chunk.constants().iter().cloned().map(Slot::from).collect()
     }
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// An operation in the script failed, e.g. negating a string.
+    Script,
+    /// The instruction budget set with `Vm::set_fuel` ran out.
+    FuelExhausted,
+    /// The run was stopped through an `InterruptHandle`.
+    Interrupted,
+    /// The memory limit set with `Vm::set_memory_limit` was exceeded.
+    OutOfMemory,
+}
+
+/// An error raised while running, displayed as `[line N] message` once
+/// the line is known.
 #[derive(Debug, thiserror::Error)]
-#[error("{}", .msg)]
+#[error("{}{msg}", .line.map_or(String::new(), |l| format!("[line {}] ", l)))]
 pub struct RuntimeError {
+    kind: ErrorKind,
     msg: String,
+    line: Option<u32>,
 }
 
 impl RuntimeError {
-    fn new(msg: String) -> Self {
-        RuntimeError { msg }
+    pub(crate) fn new(msg: String) -> Self {
+        RuntimeError {
+            kind: ErrorKind::Script,
+            msg,
+            line: None,
+        }
+    }
+
+    fn fuel_exhausted() -> Self {
+        RuntimeError {
+            kind: ErrorKind::FuelExhausted,
+            msg: "instruction budget exhausted".to_string(),
+            line: None,
+        }
+    }
+
+    fn interrupted() -> Self {
+        RuntimeError {
+            kind: ErrorKind::Interrupted,
+            msg: "interrupted".to_string(),
+            line: None,
+        }
+    }
+
+    fn out_of_memory() -> Self {
+        RuntimeError {
+            kind: ErrorKind::OutOfMemory,
+            msg: "out of memory".to_string(),
+            line: None,
+        }
     }
 
-    fn with_line(&self, line: u32) -> Self {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    /// The line of the instruction that failed, if the error was raised
+    /// by one.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    fn with_line(self, line: u32) -> Self {
         RuntimeError {
-            msg: format!("[line {}] {}", line, self.msg),
+            line: Some(line),
+            ..self
         }
     }
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,228 @@
+use super::{ErrorKind, Result, Vm};
+use crate::Chunk;
+
+// Hand-assembled so the constant folder cannot shorten them.
+const SUM: &str = "
+    OP_CONSTANT 1
+    OP_CONSTANT 2
+    OP_ADD
+    OP_CONSTANT 3
+    OP_ADD
+    OP_RETURN
+";
+
+const CONCAT: &str = "
+    OP_CONSTANT 'abc'
+    OP_CONSTANT 'def'
+    OP_ADD
+    OP_CONSTANT 'g'
+    OP_ADD
+    OP_RETURN
+";
+
+fn run(vm: &mut Vm, listing: &str) -> Result<()> {
+    vm.interpret_chunk(Chunk::assemble(listing).unwrap())
+}
+
+#[test]
+fn fuel_exhausted() {
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(3));
+    let err = run(&mut vm, SUM).unwrap_err();
+    assert_eq!(ErrorKind::FuelExhausted, err.kind());
+    assert_eq!(Some(0), vm.fuel());
+    assert!(vm.is_suspended());
+}
+
+#[test]
+fn resume_with_more_fuel() -> Result<()> {
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(2));
+    assert!(run(&mut vm, SUM).is_err());
+    let err = vm.resume(1).unwrap_err();
+    assert_eq!(ErrorKind::FuelExhausted, err.kind());
+    vm.resume(10)?;
+    assert!(!vm.is_suspended());
+    assert_eq!(Some(7), vm.fuel());
+    assert_eq!(ErrorKind::Script, vm.resume(1).unwrap_err().kind());
+
+    Ok(())
+}
+
+#[test]
+fn unlimited_fuel() -> Result<()> {
+    let mut vm = Vm::init();
+    run(&mut vm, SUM)?;
+    assert_eq!(None, vm.fuel());
+
+    Ok(())
+}
+
+#[test]
+fn interpret_discards_suspended_run() -> Result<()> {
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(1));
+    assert!(run(&mut vm, SUM).is_err());
+    vm.set_fuel(None);
+    vm.interpret("-1".to_string())?;
+    assert!(!vm.is_suspended());
+    assert!(vm.stack.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn interrupted_run() -> Result<()> {
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(1));
+    assert!(run(&mut vm, SUM).is_err());
+
+    let handle = vm.interrupt_handle();
+    std::thread::spawn(move || handle.interrupt())
+        .join()
+        .unwrap();
+    let err = vm.resume(10).unwrap_err();
+    assert_eq!(ErrorKind::Interrupted, err.kind());
+    assert!(!vm.is_suspended());
+    assert!(vm.stack.is_empty());
+
+    run(&mut vm, SUM)?;
+
+    Ok(())
+}
+
+#[test]
+fn memory_limit() -> Result<()> {
+    let mut vm = Vm::init();
+    run(&mut vm, CONCAT)?;
+    let stats = vm.memory_stats();
+    assert_eq!(0, stats.current);
+    assert!(stats.peak > 6 + 7);
+
+    vm.set_memory_limit(Some(stats.peak - 1));
+    let err = run(&mut vm, CONCAT).unwrap_err();
+    assert_eq!(ErrorKind::OutOfMemory, err.kind());
+    assert!(vm.stack.is_empty());
+    assert_eq!(0, vm.memory_stats().current);
+
+    vm.set_memory_limit(Some(stats.peak));
+    run(&mut vm, CONCAT)?;
+
+    Ok(())
+}
+
+#[test]
+fn memory_held_while_suspended() {
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(3));
+    assert!(run(&mut vm, CONCAT).is_err());
+    let stats = vm.memory_stats();
+    assert!(stats.current > 6);
+    assert_eq!(stats.current, stats.peak);
+}
+
+#[test]
+fn reset_keeps_settings() {
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(3));
+    vm.set_memory_limit(Some(100));
+    assert!(run(&mut vm, CONCAT).is_err());
+    vm.reset();
+    assert!(!vm.is_suspended());
+    assert_eq!(0, vm.memory_stats().current);
+    assert_eq!(0, vm.memory_stats().peak);
+    assert_eq!(Some(100), vm.memory_stats().limit);
+    assert_eq!(Some(0), vm.fuel());
+    assert!(vm.resume(10).is_err());
+}
+
+#[test]
+fn args() -> Result<()> {
+    let mut vm = Vm::init();
+    assert!(vm.args().is_empty());
+    vm.set_args(vec!["a".to_string(), "b c".to_string()]);
+    run(&mut vm, SUM)?;
+    vm.reset();
+    assert_eq!(["a", "b c"], vm.args());
+
+    Ok(())
+}
+
+#[test]
+fn stack_limit() -> Result<()> {
+    let mut vm = Vm::with_stack_capacity(2);
+    vm.set_stack_limit(2);
+    run(&mut vm, SUM)?;
+
+    let nested = "
+        OP_CONSTANT 1
+        OP_CONSTANT 2
+        OP_CONSTANT 3
+        OP_MULTIPLY
+        OP_ADD
+        OP_RETURN
+    ";
+    let err = run(&mut vm, nested).unwrap_err();
+    assert_eq!(ErrorKind::Script, err.kind());
+    assert_eq!("stack overflow", err.message());
+    assert_eq!(Some(1), err.line());
+    assert_eq!(0, vm.memory_stats().current);
+
+    vm.set_stack_limit(3);
+    run(&mut vm, nested)?;
+
+    Ok(())
+}
+
+#[test]
+fn superinstructions() -> Result<()> {
+    let mut vm = Vm::init();
+    run(&mut vm, "OP_CONSTANT1\nOP_ADD_CONSTANT 2\nOP_RETURN")?;
+    run(&mut vm, "OP_CONSTANT 'a'\nOP_ADD_CONSTANT 'b'\nOP_RETURN")?;
+
+    let err = run(&mut vm, "1 OP_CONSTANT0\n2 OP_ADD_CONSTANT 'b'\nOP_RETURN")
+        .unwrap_err();
+    assert_eq!(ErrorKind::Script, err.kind());
+    assert!(err.to_string().contains("[line 2]"));
+
+    Ok(())
+}
+
+#[test]
+fn extended_operands() -> Result<()> {
+    let mut listing = String::from("OP_NIL\n");
+    for i in 0..300 {
+        listing.push_str(&format!("OP_CONSTANT {i}\nOP_EQUAL\n"));
+    }
+    listing.push_str("OP_NEGATE\nOP_RETURN\n");
+
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(600));
+    let err = run(&mut vm, &listing).unwrap_err();
+    assert_eq!(ErrorKind::FuelExhausted, err.kind());
+    // Stops after the last OP_CONSTANT, whose index is extended.
+    vm.resume(1).unwrap_err();
+    let err = vm.resume(10).unwrap_err();
+    assert_eq!(ErrorKind::Script, err.kind());
+    assert_eq!(Some(9), vm.fuel());
+
+    Ok(())
+}
+
+#[cfg(feature = "bench_mode")]
+#[test]
+fn bench_checks_limits() {
+    let chunk = Chunk::assemble(CONCAT).unwrap();
+    let mut vm = Vm::init();
+    vm.set_fuel(Some(100));
+    vm.bench(&chunk, 10).unwrap_err();
+
+    // Enough for one run, so each iteration must start from nothing.
+    vm.set_fuel(None);
+    vm.set_memory_limit(Some(13));
+    vm.bench(&chunk, 10).unwrap();
+
+    vm.set_stack_limit(1);
+    let err = vm.bench(&chunk, 10).unwrap_err();
+    assert_eq!("stack overflow", err.message());
+}